@@ -0,0 +1,109 @@
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use unicode_segmentation::UnicodeSegmentation;
+
+lazy_static! {
+    static ref ENGLISH: Stemmer = Stemmer::create(Algorithm::English);
+}
+
+/// Turns text into the terms we store in (and look up from) the index.
+///
+/// Documents and queries must always go through the same analyzer, otherwise
+/// queries will silently miss documents. That's why the `Index` owns its
+/// analyzer, and query parsing borrows it from there.
+pub trait Analyzer {
+    /// The terms of `text`, each with its offset among the words of `text`.
+    /// Words the analyzer drops still take up an offset, so phrases keep their shape.
+    fn tokens(&self, text: &str) -> Vec<(u32, String)>;
+
+    /// Just the terms of `text`, in order.
+    fn analyze(&self, text: &str) -> Vec<String> {
+        self.tokens(text)
+            .into_iter()
+            .map(|(_, term)| term)
+            .collect()
+    }
+}
+
+/// Splits text into Unicode words (dropping punctuation and whitespace),
+/// lowercases them, drops stop words, and optionally stems what's left.
+///
+/// Stop words are removed but leave a gap, so the phrase "tail at scale" still
+/// matches "tail of scale", but not "tail of the scale".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StandardAnalyzer {
+    /// Reduce words to their English stem, so "caches" matches "cache".
+    pub stem: bool,
+
+    /// Words too common to be worth indexing. Matched after lowercasing.
+    pub stop_words: HashSet<String>,
+}
+
+impl Default for StandardAnalyzer {
+    fn default() -> Self {
+        StandardAnalyzer {
+            stem: true,
+            stop_words: ENGLISH_STOP_WORDS.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+}
+
+impl StandardAnalyzer {
+    /// An analyzer that only segments and lowercases.
+    pub fn plain() -> Self {
+        StandardAnalyzer {
+            stem: false,
+            stop_words: HashSet::new(),
+        }
+    }
+}
+
+impl Analyzer for StandardAnalyzer {
+    fn tokens(&self, text: &str) -> Vec<(u32, String)> {
+        text.unicode_words()
+            .map(|word| word.to_lowercase())
+            .zip(0..)
+            .filter(|(word, _)| !self.stop_words.contains(word))
+            .map(|(word, offset)| {
+                if self.stem {
+                    (offset, ENGLISH.stem(&word).into_owned())
+                } else {
+                    (offset, word)
+                }
+            })
+            .collect()
+    }
+}
+
+const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_punctuation_stop_words_and_suffixes() {
+        assert_eq!(
+            StandardAnalyzer::default().analyze("The caches' latency, at scale!"),
+            ["cach", "latenc", "scale"]
+        );
+        assert_eq!(
+            StandardAnalyzer::plain().analyze("The caches' latency, at “scale”!"),
+            ["the", "caches", "latency", "at", "scale"]
+        );
+    }
+
+    #[test]
+    fn stop_words_leave_gaps() {
+        assert_eq!(
+            StandardAnalyzer::default().tokens("Tail at scale"),
+            [(0, "tail".to_string()), (2, "scale".to_string())]
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use folklore::*;
+
+use std::path::Path;
+use std::time::Instant;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = gflags::parse();
+    println!("Binary arguments: {:#?}", args);
+
+    let config = config::load_or_exit();
+
+    let started = Instant::now();
+    let index = index::build_index(Path::new(net::OUTPUT_DIR.flag), config.analyzer)?;
+    println!(
+        "Indexed {} documents and {} words in {:?}.",
+        index.document_codes.len(),
+        index.word_codes.len(),
+        started.elapsed()
+    );
+
+    let snapshot_path = Path::new(snapshot::SNAPSHOT_PATH.flag);
+    snapshot::save(&index, snapshot_path)?;
+    println!("Wrote index snapshot to {}", snapshot_path.display());
+
+    Ok(())
+}
@@ -5,9 +5,8 @@ use folklore::*;
 
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
-use gflags;
 use reqwest::redirect::Policy;
-use std::collections::HashSet;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time;
 use tokio::task;
@@ -17,14 +16,16 @@ lazy_static! {
     static ref CLIENT: reqwest::Client = reqwest::Client::builder()
         .connect_timeout(time::Duration::from_millis(4096))
         .timeout(time::Duration::from_secs(64))
-        .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36")
+        .user_agent(net::USER_AGENT.flag)
         .redirect(Policy::none())
         .build()
         .unwrap();
-    static ref CONFIG: Config = toml::from_str(std::include_str!("../../data.toml"))
-        .expect("Failed to deserialized config file.");
+    static ref CONFIG: Config = config::load_or_exit();
+    static ref ROBOTS: robots::RobotsCache = robots::RobotsCache::new(net::USER_AGENT.flag);
+    static ref SCHEDULER: scheduler::Scheduler =
+        scheduler::Scheduler::new(scheduler::MAX_CONCURRENT_FETCHES.flag, &CONFIG.websites);
 
-    static ref ALLOWED_DOMAINS: HashSet<String> = CONFIG.websites.iter().map(|w| Url::parse(&w.url).unwrap().domain().unwrap().to_string()).collect();
+    static ref SCOPE: scope::ScopePolicy = scope::ScopePolicy::new(&CONFIG.websites);
 }
 
 #[tokio::main]
@@ -32,65 +33,159 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = gflags::parse();
     println!("Binary arguments: {:#?}", args);
 
-    run().await;
+    if args.first() == Some(&"check-config") {
+        check_config();
+        return Ok(());
+    }
+
+    run().await?;
     Ok(())
 }
 
-async fn run() {
-    let visited = Arc::new(Mutex::new(HashSet::new()));
-    let crawl_stack: Arc<Mutex<Vec<(reqwest::Url, bool, Arc<Mutex<HashSet<reqwest::Url>>>)>>> =
-        Arc::new(Mutex::new(
-            CONFIG
-                .websites
-                .iter()
-                .map(|w| {
-                    (
-                        Url::parse(&w.url).unwrap(),
-                        w.recursively_crawl,
-                        visited.clone(),
-                    )
-                })
-                .collect(),
-        ));
-
-    let mut handles = FuturesUnordered::<tokio::task::JoinHandle<()>>::new();
+/// Exits non-zero, listing every problem, unless the --config file is valid.
+fn check_config() {
+    let config = config::load_or_exit();
+    println!(
+        "{} is valid: {} websites, {} repositories and {} other sources.",
+        config::CONFIG.flag,
+        config.websites.len(),
+        config.repositories.len(),
+        config.sources.len()
+    );
+}
+
+async fn run() -> std::io::Result<()> {
+    let mut frontier = frontier::Frontier::open(Path::new(frontier::FRONTIER_PATH.flag))?;
+    if frontier.is_finished() {
+        println!("Starting a new crawl.");
+        frontier.reset()?;
+        start(&mut frontier).await?;
+    } else {
+        println!("Resuming the crawl in {}.", frontier::FRONTIER_PATH.flag);
+    }
+    let frontier = Arc::new(Mutex::new(frontier));
+
+    let mut handles = FuturesUnordered::<tokio::task::JoinHandle<std::io::Result<()>>>::new();
 
     loop {
-        let mut crawl_envelope = crawl_stack.lock().unwrap().pop();
+        let entry = frontier.lock().unwrap().pop();
+        let entry = match entry {
+            Some(entry) => entry,
+            // Nothing is queued right now, but the crawls in flight may queue more.
+            None => match handles.next().await {
+                Some(Ok(result)) => {
+                    result?;
+                    continue;
+                }
+                // The entry was never marked done, so it's crawled again on resume.
+                Some(Err(e)) => {
+                    eprintln!("A crawl task failed: {}", e);
+                    continue;
+                }
+                None => break,
+            },
+        };
 
-        if crawl_envelope.is_none() {
-            while let Some(_doc) = handles.next().await {
-                println!("Future completed.")
+        let website = match CONFIG.websites.iter().find(|w| w.url == entry.website) {
+            Some(website) => website,
+            None => {
+                println!("{} is no longer configured, dropping {}", entry.website, entry.url);
+                frontier.lock().unwrap().done(&entry.url)?;
+                continue;
             }
+        };
+        let frontier = frontier.clone();
 
-            if crawl_stack.lock().unwrap().is_empty() {
-                break;
-            } else {
-                crawl_envelope = crawl_stack.lock().unwrap().pop();
-            }
-        }
+        handles.push(task::spawn(async move {
+            let documents =
+                net::crawl(&CLIENT, &entry, website, &SCOPE, &ROBOTS, &SCHEDULER, &frontier).await;
 
-        let crawl_envelope = crawl_envelope.unwrap();
-        let crawl_stack_ptr = crawl_stack.clone();
+            let mut frontier = frontier.lock().unwrap();
+            for document in documents {
+                // URLs that redirected here are as good as visited.
+                for alias in &document.aliases {
+                    frontier.visit(alias)?;
+                }
 
-        handles.push(task::spawn(async move {
-            for document in net::crawl(&CLIENT, crawl_envelope.0, &ALLOWED_DOMAINS).await {
                 let mut visited_url = Url::parse(&document.url).unwrap();
                 visited_url.set_query(None);
                 visited_url.set_fragment(None);
-                if crawl_envelope.2.lock().unwrap().insert(visited_url.clone()) && crawl_envelope.1
-                {
-                    crawl_stack_ptr.clone().lock().unwrap().push((
-                        visited_url,
-                        true,
-                        crawl_envelope.2.clone(),
-                    ));
+                let depth = if document.url == entry.url { entry.depth } else { entry.depth + 1 };
+                if website.recursively_crawl && website.follows_links_from(depth) {
+                    frontier.push(frontier::Entry {
+                        url: visited_url.to_string(),
+                        website: website.url.clone(),
+                        depth,
+                        priority: priority(depth),
+                        published_at_linux_epoch_secs: document.published_at_linux_epoch_secs,
+                        author: None,
+                        tags: vec![],
+                    })?;
+                } else {
+                    frontier.visit(visited_url.as_str())?;
                 }
             }
+            frontier.done(&entry.url)
         }));
-
-        println!("Finished all the crawling. Starting another generation.");
     }
 
     println!("Finished all the crawling.");
+    Ok(())
+}
+
+/// Saves the documents every source already has, and queues the pages they
+/// want crawled: websites' roots, and the pages their sitemaps and feeds list.
+async fn start(frontier: &mut frontier::Frontier) -> std::io::Result<()> {
+    let crawler = source::Crawler {
+        client: &CLIENT,
+        scheduler: &SCHEDULER,
+        robots: &ROBOTS,
+        scope: &SCOPE,
+        websites: &CONFIG.websites,
+    };
+    let sources = CONFIG.sources();
+    let produced = futures::future::join_all(sources.iter().map(|source| async move {
+        (source.name(), source.items(crawler).await)
+    }))
+    .await;
+
+    for (name, items) in produced {
+        let items = match items {
+            Ok(items) => items,
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", name, err);
+                continue;
+            }
+        };
+        println!("{} gave {} items", name, items.len());
+
+        for item in items {
+            match item {
+                source::Item::Document(document) => {
+                    if let Err(e) = net::save_document(&document) {
+                        eprintln!("Failed to save {} from {}: {}", document.url, name, e);
+                    }
+                }
+                source::Item::Seed { website, seed, depth } => {
+                    frontier.push(frontier::Entry {
+                        url: seed.url.to_string(),
+                        website,
+                        depth,
+                        priority: priority(depth),
+                        published_at_linux_epoch_secs: seed.published_at_linux_epoch_secs,
+                        author: seed.author,
+                        tags: seed.tags,
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Crawl breadth-first, so that an interrupted or depth-limited crawl has
+/// covered the pages closest to each website's root.
+fn priority(depth: u32) -> i64 {
+    -i64::from(depth)
 }
@@ -0,0 +1,41 @@
+use folklore::*;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+gflags::define! {
+    /// The address the search server listens on.
+    --listen_addr <ADDR> = "127.0.0.1:8080"
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = gflags::parse();
+    println!("Binary arguments: {:#?}", args);
+
+    let config = config::load_or_exit();
+
+    let started = Instant::now();
+    let index = snapshot::load(Path::new(snapshot::SNAPSHOT_PATH.flag))?;
+    println!(
+        "Loaded {} documents from {} in {:?}.",
+        index.document_codes.len(),
+        snapshot::SNAPSHOT_PATH.flag,
+        started.elapsed()
+    );
+
+    let state = Arc::new(server::SearchState {
+        index,
+        output_dir: PathBuf::from(net::OUTPUT_DIR.flag),
+        websites: config.websites,
+    });
+
+    let addr = LISTEN_ADDR.flag.parse()?;
+    println!("Serving search on http://{}", addr);
+    axum::Server::bind(&addr)
+        .serve(server::router(state).into_make_service())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::discovery::{self, Seed};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use url::Url;
+
+/// The tag namespaces worth keeping as tags. The others name the author or
+/// content type, which every article has, or flag articles as new.
+const TAG_NAMESPACES: &[&str] = &[
+    "amazon-redwood#content-category",
+    "GLOBAL#level",
+    "GLOBAL#use-case",
+];
+
+const AUTHOR_NAMESPACE: &str = "amazon-redwood#content-author";
+
+/// A response from the AWS directory API, e.g.
+/// `adhoc-sources/aws-builders-library/data.json`.
+#[derive(Deserialize)]
+struct Directory {
+    items: Vec<DirectoryItem>,
+}
+
+#[derive(Deserialize)]
+struct DirectoryItem {
+    item: Item,
+    #[serde(default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    #[serde(rename = "additionalFields")]
+    fields: Fields,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fields {
+    headline_url: Option<String>,
+    /// e.g. "Author: David Yanacek" or "Authors: Becky Weiss, Mike Furr".
+    content_author: Option<String>,
+    /// e.g. "2022-12-01".
+    published_date: Option<String>,
+    sort_date: Option<String>,
+    /// e.g. "December 2022".
+    date_text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tag {
+    tag_namespace_id: String,
+    description: String,
+}
+
+/// Reads the articles out of a Builders' Library directory listing, as seeds
+/// with their author, publication date and tags.
+pub fn parse(json: &str) -> serde_json::Result<Vec<Seed>> {
+    let directory: Directory = serde_json::from_str(json)?;
+
+    Ok(directory
+        .items
+        .into_iter()
+        .filter_map(|DirectoryItem { item, tags }| {
+            let fields = item.fields;
+            let mut url = Url::parse(fields.headline_url.as_deref()?.trim()).ok()?;
+            // Drop the `?did=ba_card&trk=ba_card` click tracking.
+            url.set_query(None);
+
+            let published_at_linux_epoch_secs = fields
+                .published_date
+                .iter()
+                .chain(&fields.sort_date)
+                .find_map(|date| discovery::parse_date(date))
+                .or_else(|| fields.date_text.as_deref().and_then(parse_month));
+
+            let author = fields
+                .content_author
+                .as_deref()
+                .map(|author| {
+                    author
+                        .trim_start_matches("Authors:")
+                        .trim_start_matches("Author:")
+                        .trim()
+                        .to_string()
+                })
+                .filter(|author| !author.is_empty())
+                .or_else(|| {
+                    let authors: Vec<&str> = tags
+                        .iter()
+                        .filter(|tag| tag.tag_namespace_id == AUTHOR_NAMESPACE)
+                        .map(|tag| tag.description.as_str())
+                        .collect();
+                    Some(authors.join(", ")).filter(|authors| !authors.is_empty())
+                });
+
+            let mut kept_tags: Vec<String> = vec![];
+            for tag in &tags {
+                let description = tag.description.trim().to_string();
+                if TAG_NAMESPACES.contains(&tag.tag_namespace_id.as_str())
+                    && !description.is_empty()
+                    && !kept_tags.contains(&description)
+                {
+                    kept_tags.push(description);
+                }
+            }
+
+            Some(Seed {
+                published_at_linux_epoch_secs,
+                author,
+                tags: kept_tags,
+                ..Seed::new(url)
+            })
+        })
+        .collect())
+}
+
+/// Parses "December 2022" as the first of that month.
+fn parse_month(date: &str) -> Option<u64> {
+    let date = chrono::NaiveDate::parse_from_str(&format!("1 {}", date.trim()), "%d %B %Y").ok()?;
+    u64::try_from(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_checked_in_listing() {
+        let seeds = parse(include_str!(
+            "../adhoc-sources/aws-builders-library/data.json"
+        ))
+        .unwrap();
+        assert_eq!(seeds.len(), 22);
+
+        assert_eq!(
+            seeds[0],
+            Seed {
+                url: Url::parse("https://aws.amazon.com/builders-library/dependency-isolation/")
+                    .unwrap(),
+                published_at_linux_epoch_secs: Some(1669852800),
+                author: Some("David Yanacek".to_string()),
+                tags: vec![
+                    "Software Delivery and Operations".to_string(),
+                    "Level 300".to_string()
+                ],
+            }
+        );
+
+        let caching = seeds
+            .iter()
+            .find(|seed| seed.url.path().contains("caching"))
+            .unwrap();
+        assert_eq!(
+            caching.url.as_str(),
+            "https://aws.amazon.com/builders-library/caching-challenges-and-strategies"
+        );
+        assert_eq!(
+            caching.author.as_deref(),
+            Some("Matt Brinkley, Jas Chhabra")
+        );
+
+        assert!(seeds
+            .iter()
+            .all(|seed| seed.published_at_linux_epoch_secs.is_some()));
+    }
+
+    #[test]
+    fn falls_back_to_the_date_text_and_author_tags() {
+        let json = r#"{"items": [{
+            "item": {"additionalFields": {
+                "headlineUrl": "https://aws.amazon.com/builders-library/cicd-pipeline/",
+                "dateText": "December 2022"
+            }},
+            "tags": [
+                {"tagNamespaceId": "amazon-redwood#content-author", "description": "Clare Liguori"},
+                {"tagNamespaceId": "amazon-redwood#level", "description": "<p>300</p>\r\n"},
+                {"tagNamespaceId": "GLOBAL#use-case", "description": "CI/CD Tools"}
+            ]
+        }, {"item": {"additionalFields": {}}}]}"#;
+
+        let seeds = parse(json).unwrap();
+        assert_eq!(seeds.len(), 1);
+        assert_eq!(seeds[0].published_at_linux_epoch_secs, Some(1669852800));
+        assert_eq!(seeds[0].author.as_deref(), Some("Clare Liguori"));
+        assert_eq!(seeds[0].tags, ["CI/CD Tools"]);
+    }
+}
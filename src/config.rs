@@ -0,0 +1,256 @@
+use crate::index::host_key;
+use crate::Config;
+use serde::Deserialize;
+use std::collections::hash_map::{Entry as Seen, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
+use url::{Host, Url};
+
+gflags::define! {
+    /// The TOML file listing the websites and other sources to index.
+    pub --config <PATH> = "data.toml"
+}
+
+/// Something wrong with a config file, and the line it's on.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Just the parts of a config that `parse` checks, with where they're written.
+#[derive(Deserialize)]
+struct Entries {
+    #[serde(default)]
+    websites: Vec<Entry>,
+    #[serde(default)]
+    repositories: Vec<Entry>,
+    #[serde(default)]
+    sources: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    url: Option<Spanned<String>>,
+    path: Option<Spanned<PathBuf>>,
+}
+
+/// Reads the config at `path`, failing with every problem found in it.
+pub fn load(path: &Path) -> io::Result<Config> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    parse(&text).map_err(|problems| {
+        let report: Vec<String> = problems
+            .iter()
+            .map(|problem| format!("{}:{}: {}", path.display(), problem.line, problem.message))
+            .collect();
+        io::Error::new(io::ErrorKind::InvalidData, report.join("\n"))
+    })
+}
+
+/// Loads the config named by --config, or says what's wrong with it and exits.
+pub fn load_or_exit() -> Config {
+    load(Path::new(CONFIG.flag)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    })
+}
+
+/// Parses a config, checking that every website and feed is an http(s) URL on
+/// a domain name, and that nothing is listed twice.
+pub fn parse(text: &str) -> Result<Config, Vec<Problem>> {
+    let entries: Entries = toml::from_str(text).map_err(|e| vec![toml_problem(&e)])?;
+    let mut problems = entries.problems(text);
+    match toml::from_str::<Config>(text) {
+        Ok(config) if problems.is_empty() => return Ok(config),
+        Ok(_) => {}
+        Err(e) => problems.push(toml_problem(&e)),
+    }
+    problems.sort_by_key(|problem| problem.line);
+    Err(problems)
+}
+
+impl Entries {
+    fn problems(&self, text: &str) -> Vec<Problem> {
+        let line = |offset: usize| text[..offset].matches('\n').count() + 1;
+        let mut entries: Vec<(&str, &Entry)> = (self.websites.iter())
+            .map(|entry| ("website", entry))
+            .chain(self.repositories.iter().map(|entry| ("git", entry)))
+            .chain(
+                self.sources
+                    .iter()
+                    .filter_map(|entry| Some((entry.kind.as_deref()?, entry))),
+            )
+            .collect();
+        // In the order they're written, so duplicates are reported where they repeat.
+        entries.sort_by_key(|(_, entry)| {
+            (entry.url.as_ref().map(Spanned::start))
+                .or_else(|| entry.path.as_ref().map(Spanned::start))
+        });
+
+        let mut problems = vec![];
+        // The line each source was first listed on, by its kind and identity.
+        let mut seen = HashMap::new();
+        for (kind, entry) in entries {
+            let crawled = matches!(kind, "website" | "feed");
+            if let Some(url) = &entry.url {
+                let checked = if crawled {
+                    check_crawled_url(url.get_ref())
+                } else {
+                    Url::parse(url.get_ref())
+                        .map(|_| ())
+                        .map_err(|e| format!("`{}` isn't a URL: {}", url.get_ref(), e))
+                };
+                if let Err(message) = checked {
+                    problems.push(Problem {
+                        line: line(url.start()),
+                        message,
+                    });
+                }
+            }
+
+            let identity = if crawled {
+                (entry.url.as_ref()).and_then(|url| Some((url.start(), site_path(url.get_ref())?)))
+            } else {
+                (entry.path.as_ref())
+                    .map(|path| (path.start(), path.get_ref().display().to_string()))
+            };
+            if let Some((offset, key)) = identity {
+                match seen.entry((kind, key)) {
+                    Seen::Occupied(first) => problems.push(Problem {
+                        line: line(offset),
+                        message: format!("duplicates the {} on line {}", kind, first.get()),
+                    }),
+                    Seen::Vacant(first) => {
+                        first.insert(line(offset));
+                    }
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Why the crawler can't, or shouldn't, start from `url`.
+fn check_crawled_url(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("`{}` isn't a URL: {}", url, e))?;
+    match parsed.host() {
+        None => Err(format!("`{}` has no host", url)),
+        Some(Host::Ipv4(_)) | Some(Host::Ipv6(_)) => {
+            Err(format!("`{}` is an IP address, not a domain name", url))
+        }
+        Some(Host::Domain(_)) if !matches!(parsed.scheme(), "http" | "https") => {
+            Err(format!("`{}` isn't an http(s) URL", url))
+        }
+        Some(Host::Domain(_)) => Ok(()),
+    }
+}
+
+/// What makes two URLs the same place to crawl from: `http://www.example.com/a/`
+/// and `https://example.com/a` are.
+fn site_path(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let mut key = host_key(url.host_str()?);
+    key.push_str(url.path().trim_end_matches('/'));
+    if let Some(query) = url.query() {
+        key.push('?');
+        key.push_str(query);
+    }
+    Some(key)
+}
+
+fn toml_problem(error: &toml::de::Error) -> Problem {
+    // The line is reported separately, so drop it from the message.
+    let message = error.to_string();
+    Problem {
+        line: error.line_col().map_or(1, |(line, _)| line + 1),
+        message: message
+            .rsplit_once(" at line ")
+            .map_or(message.as_str(), |(message, _)| message)
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(text: &str) -> Vec<(usize, String)> {
+        parse(text)
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|problem| (problem.line, problem.message))
+            .collect()
+    }
+
+    #[test]
+    fn reports_every_bad_entry_by_line() {
+        let text = r#"
+[[websites]]
+url = "https://danluu.com"
+
+[[websites]]
+url = "http://www.danluu.com/"
+
+[[websites]]
+url = "http://10.0.0.1/blog"
+
+[[websites]]
+url = "danluu.com"
+
+[[sources]]
+type = "feed"
+url = "file:///tmp/feed.xml"
+
+[[sources]]
+type = "git"
+path = "/src/folklore"
+
+[[repositories]]
+path = "/src/folklore"
+url = "not a url"
+"#;
+
+        assert_eq!(
+            problems(text),
+            [
+                (6, "duplicates the website on line 3".to_string()),
+                (
+                    9,
+                    "`http://10.0.0.1/blog` is an IP address, not a domain name".to_string()
+                ),
+                (
+                    12,
+                    "`danluu.com` isn't a URL: relative URL without a base".to_string()
+                ),
+                (16, "`file:///tmp/feed.xml` has no host".to_string()),
+                (23, "duplicates the git on line 20".to_string()),
+                (
+                    24,
+                    "`not a url` isn't a URL: relative URL without a base".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_what_toml_rejects() {
+        assert_eq!(
+            problems("[[websites]]\nurl = \"https://danluu.com\"\nmax_depth = \"two\"\n"),
+            [(
+                3,
+                "invalid type: string \"two\", expected u32 for key `websites.max_depth`"
+                    .to_string()
+            )]
+        );
+        assert_eq!(
+            problems("[[websites]]\nurl = \"https://danluu.com\n"),
+            [(2, "newline in string found".to_string())]
+        );
+        assert!(parse(include_str!("../data.toml")).is_ok());
+    }
+}
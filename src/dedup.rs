@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How many words each shingle spans.
+const SHINGLE_LEN: usize = 3;
+
+/// Documents whose SimHashes differ in at most this many bits are near-duplicates.
+pub const MAX_DISTANCE: u32 = 3;
+
+/// The 64-bit SimHash of some texts, over overlapping word shingles. Texts
+/// that share most of their shingles get hashes that differ in only a few bits.
+///
+/// Returns None for texts with no words, which are near-duplicates of nothing.
+pub fn simhash<S: AsRef<str>>(texts: &[S]) -> Option<u64> {
+    let words: Vec<String> = texts
+        .iter()
+        .flat_map(|text| text.as_ref().unicode_words())
+        .map(|word| word.to_lowercase())
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+
+    let mut weights = [0i64; 64];
+    for shingle in words.windows(SHINGLE_LEN.min(words.len())) {
+        let hash = fnv1a(shingle);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |hash, (bit, _)| hash | 1 << bit),
+    )
+}
+
+/// Groups the `(id, simhash)`s into clusters of near-duplicates. Only clusters
+/// of two or more are returned.
+///
+/// Any two hashes within `MAX_DISTANCE` bits agree exactly on at least one of
+/// `MAX_DISTANCE + 1` bands, so only hashes sharing a band are compared.
+pub fn near_duplicates(hashes: &[(u32, u64)]) -> Vec<Vec<u32>> {
+    const BANDS: u32 = MAX_DISTANCE + 1;
+    const BAND_BITS: u32 = 64 / BANDS;
+
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    for band in 0..BANDS {
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, (_, hash)) in hashes.iter().enumerate() {
+            let key = hash >> (band * BAND_BITS) & ((1 << BAND_BITS) - 1);
+            buckets.entry(key).or_default().push(i);
+        }
+
+        for bucket in buckets.values() {
+            for (n, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[n + 1..] {
+                    if (hashes[a].1 ^ hashes[b].1).count_ones() <= MAX_DISTANCE {
+                        let (root_a, root_b) = (find(&mut parents, a), find(&mut parents, b));
+                        parents[root_a] = root_b;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<u32>> = HashMap::new();
+    for (i, (id, _)) in hashes.iter().enumerate() {
+        let root = find(&mut parents, i);
+        clusters.entry(root).or_default().push(*id);
+    }
+    clusters.into_values().filter(|c| c.len() > 1).collect()
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// FNV-1a, so that hashes are stable across builds and platforms.
+fn fnv1a(words: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in words.join(" ").bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POST: &str = "Most latency in a datacenter comes from the tail. A request that fans out \
+        to a hundred servers is as slow as the slowest of them, so the p99 of each server becomes \
+        the median of the whole request. Hedged requests and tied requests are two ways to cut it.";
+
+    #[test]
+    fn near_identical_texts_collapse() {
+        let post = simhash(&[POST]).unwrap();
+        let mirrored = simhash(&["Home | Archive", POST]).unwrap();
+        let other = simhash(&[
+            "Caches are fast because most accesses hit a small working set, \
+            and they are slow when the working set outgrows them.",
+        ])
+        .unwrap();
+
+        assert!((post ^ mirrored).count_ones() <= MAX_DISTANCE);
+        assert!((post ^ other).count_ones() > MAX_DISTANCE);
+        assert_eq!(simhash::<&str>(&[]), None);
+
+        let mut clusters = near_duplicates(&[(0, post), (1, other), (2, mirrored), (3, post)]);
+        clusters[0].sort_unstable();
+        assert_eq!(clusters, vec![vec![0, 2, 3]]);
+    }
+}
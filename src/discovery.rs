@@ -0,0 +1,456 @@
+use crate::net::{self, FetchFailure};
+use crate::robots::RobotsCache;
+use crate::scheduler::Scheduler;
+use crate::scope::ScopePolicy;
+use crate::Website;
+use select::document::Document;
+use select::predicate::{Attr, Name, Predicate};
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use url::Url;
+
+/// The most sitemaps we read for one website, counting those in sitemap indexes.
+const MAX_SITEMAPS: usize = 64;
+
+/// A URL to start crawling from, with what the sitemap, feed or directory
+/// listing it says about the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Seed {
+    pub url: Url,
+    pub published_at_linux_epoch_secs: Option<u64>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Seed {
+    pub fn new(url: Url) -> Self {
+        Seed {
+            url,
+            published_at_linux_epoch_secs: None,
+            author: None,
+            tags: vec![],
+        }
+    }
+}
+
+/// What a sitemap lists: pages, or (for a sitemap index) more sitemaps.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sitemap {
+    pub pages: Vec<Seed>,
+    pub sitemaps: Vec<Url>,
+}
+
+/// An entry of an RSS or Atom feed: the page it links to, and what the feed
+/// says about it.
+#[derive(Debug, Default, PartialEq)]
+pub struct FeedEntry {
+    pub seed: Option<Seed>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// The entry's content or summary, which is usually HTML.
+    pub summary: Option<String>,
+}
+
+/// Finds every page of `website` that its sitemaps and RSS/Atom feeds list.
+/// Sitemaps come from its robots.txt and `/sitemap.xml`, and feeds from the
+/// `<link rel="alternate">`s on its front page.
+pub async fn discover(
+    client: &reqwest::Client,
+    scheduler: &Scheduler,
+    robots: &RobotsCache,
+    website: &Website,
+    scope: &ScopePolicy,
+) -> Vec<Seed> {
+    let root = match Url::parse(&website.url) {
+        Ok(root) => root,
+        Err(_) => return vec![],
+    };
+
+    let mut sitemaps: VecDeque<Url> = robots
+        .get(client, scheduler, &root)
+        .await
+        .sitemaps
+        .iter()
+        .filter_map(|s| Url::parse(s).ok())
+        .collect();
+    sitemaps.extend(root.join("/sitemap.xml"));
+
+    let mut seeds = vec![];
+    let mut seen_sitemaps = HashSet::new();
+    while let Some(sitemap) = sitemaps.pop_front() {
+        if seen_sitemaps.len() == MAX_SITEMAPS || !seen_sitemaps.insert(sitemap.clone()) {
+            continue;
+        }
+        if let Some((url, body)) = fetch_listing(client, scheduler, robots, &sitemap, scope).await {
+            let parsed = parse_sitemap(&body, &url);
+            seeds.extend(parsed.pages);
+            sitemaps.extend(parsed.sitemaps);
+        }
+    }
+
+    if let Some((url, body)) = fetch_listing(client, scheduler, robots, &root, scope).await {
+        for feed in feed_links(&body, &url) {
+            if let Some((url, body)) = fetch_listing(client, scheduler, robots, &feed, scope).await
+            {
+                seeds.extend(parse_feed(&body, &url));
+            }
+        }
+    }
+
+    // Sitemaps often cover a whole host, and a website may only be part of it.
+    let mut seen = HashSet::new();
+    seeds.retain(|seed| website.in_scope(&seed.url) && seen.insert(seed.url.clone()));
+    println!("Discovered {} seeds for {}", seeds.len(), website.url);
+    seeds
+}
+
+async fn fetch_listing(
+    client: &reqwest::Client,
+    scheduler: &Scheduler,
+    robots: &RobotsCache,
+    url: &Url,
+    scope: &ScopePolicy,
+) -> Option<(Url, String)> {
+    if !robots.is_allowed(client, scheduler, url).await {
+        return None;
+    }
+
+    match net::fetch_text(client, scheduler, url, scope).await {
+        Ok(fetched) => Some(fetched),
+        // Most sites simply don't have a sitemap; that's not worth shouting about.
+        Err(FetchFailure::Status(404)) => None,
+        Err(failure) => {
+            eprintln!("Failed to fetch {}: {:?}", url, failure);
+            None
+        }
+    }
+}
+
+/// Parses a sitemap (`<urlset>`) or sitemap index (`<sitemapindex>`). A page's
+/// `<lastmod>` is when it last changed, not when it was published, so it's
+/// left out.
+pub fn parse_sitemap(xml: &str, base: &Url) -> Sitemap {
+    let mut sitemap = Sitemap::default();
+    let doc = match roxmltree::Document::parse(xml) {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("Failed to parse sitemap {}: {}", base, e);
+            return sitemap;
+        }
+    };
+
+    for entry in doc.root_element().children().filter(|n| n.is_element()) {
+        let loc = match child_text(entry, "loc").and_then(|loc| base.join(loc.trim()).ok()) {
+            Some(loc) => loc,
+            None => continue,
+        };
+
+        match entry.tag_name().name() {
+            "url" => sitemap.pages.push(Seed::new(loc)),
+            "sitemap" if !loc.path().ends_with(".gz") => sitemap.sitemaps.push(loc),
+            _ => {}
+        }
+    }
+
+    sitemap
+}
+
+/// Parses the entries of an RSS or Atom feed.
+pub fn parse_feed(xml: &str, base: &Url) -> Vec<Seed> {
+    parse_feed_entries(xml, base)
+        .into_iter()
+        .filter_map(|entry| entry.seed)
+        .collect()
+}
+
+/// Like `parse_feed`, but keeps each entry's title, author and summary. An
+/// entry without a usable link has no `seed`.
+pub fn parse_feed_entries(xml: &str, base: &Url) -> Vec<FeedEntry> {
+    let doc = match roxmltree::Document::parse(xml) {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("Failed to parse feed {}: {}", base, e);
+            return vec![];
+        }
+    };
+
+    doc.descendants()
+        .filter(|n| n.is_element() && matches!(n.tag_name().name(), "item" | "entry"))
+        .map(|entry| {
+            let link = child_text(entry, "link")
+                .filter(|l| !l.trim().is_empty())
+                .or_else(|| {
+                    // Atom puts the link in an attribute, and may have several.
+                    entry
+                        .children()
+                        .filter(|n| n.tag_name().name() == "link")
+                        .find(|n| n.attribute("rel").map_or(true, |rel| rel == "alternate"))
+                        .and_then(|n| n.attribute("href"))
+                });
+            let text = |names: &[&str]| {
+                names
+                    .iter()
+                    .find_map(|name| child_text(entry, name))
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .map(str::to_string)
+            };
+
+            FeedEntry {
+                seed: link
+                    .and_then(|link| base.join(link.trim()).ok())
+                    .map(|url| Seed {
+                        // Not Atom's `updated`: that's when the entry last
+                        // changed, which would make every edited old post new.
+                        published_at_linux_epoch_secs: text(&["pubDate", "published"])
+                            .and_then(|date| parse_date(&date)),
+                        ..Seed::new(url)
+                    }),
+                title: text(&["title"]),
+                // RSS names the author in `author` or `dc:creator`; Atom in `author/name`.
+                author: text(&["creator", "author"]).or_else(|| {
+                    entry
+                        .children()
+                        .find(|n| n.tag_name().name() == "author")
+                        .and_then(|author| child_text(author, "name"))
+                        .map(|name| name.trim().to_string())
+                }),
+                // `content:encoded` and Atom's `content` hold the whole post.
+                summary: text(&["encoded", "content", "description", "summary"]),
+            }
+        })
+        .collect()
+}
+
+/// The RSS and Atom feeds an HTML page advertises with `<link rel="alternate">`.
+pub fn feed_links(html: &str, base: &Url) -> Vec<Url> {
+    Document::from(html)
+        .find(Name("link").and(Attr("rel", "alternate")))
+        .filter(|node| {
+            matches!(
+                node.attr("type"),
+                Some("application/rss+xml") | Some("application/atom+xml")
+            )
+        })
+        .filter_map(|node| base.join(node.attr("href")?).ok())
+        .collect()
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.tag_name().name() == name)
+        .and_then(|n| n.text())
+}
+
+/// Parses the dates feeds and sitemaps use: RFC 3339 (Atom, sitemaps), RFC 2822
+/// (RSS), or a bare `YYYY-MM-DD`.
+pub fn parse_date(date: &str) -> Option<u64> {
+    let date = date.trim();
+    let secs = chrono::DateTime::parse_from_rfc3339(date)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(date))
+        .map(|d| d.timestamp())
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
+        })
+        .ok()?;
+    u64::try_from(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_sitemaps_and_sitemap_indexes() {
+        let base = url("https://example.com/sitemap.xml");
+        let urlset = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>https://example.com/posts/one</loc><lastmod>2023-11-13</lastmod></url>
+              <url><loc> https://example.com/posts/two </loc></url>
+            </urlset>"#;
+        assert_eq!(
+            parse_sitemap(urlset, &base).pages,
+            vec![
+                Seed::new(url("https://example.com/posts/one")),
+                Seed::new(url("https://example.com/posts/two")),
+            ]
+        );
+
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap><loc>https://example.com/sitemap-posts.xml</loc></sitemap>
+              <sitemap><loc>https://example.com/sitemap-old.xml.gz</loc></sitemap>
+            </sitemapindex>"#;
+        assert_eq!(
+            parse_sitemap(index, &base),
+            Sitemap {
+                pages: vec![],
+                sitemaps: vec![url("https://example.com/sitemap-posts.xml")],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_rss_and_atom_feeds() {
+        let base = url("https://example.com/feed.xml");
+        let rss = r#"<rss version="2.0"><channel>
+              <title>Example</title><link>https://example.com/</link>
+              <item><title>One</title><link>https://example.com/posts/one</link>
+                <pubDate>Mon, 13 Nov 2023 00:00:00 +0000</pubDate></item>
+            </channel></rss>"#;
+        assert_eq!(
+            parse_feed(rss, &base),
+            vec![Seed {
+                published_at_linux_epoch_secs: Some(1699833600),
+                ..Seed::new(url("https://example.com/posts/one"))
+            }]
+        );
+
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <entry>
+                <link rel="replies" href="/posts/two#comments"/>
+                <link href="/posts/two"/>
+                <published>2023-11-13T01:00:00+01:00</published>
+                <updated>2024-01-01T00:00:00Z</updated>
+              </entry>
+              <entry>
+                <link href="/posts/three"/>
+                <updated>2024-01-01T00:00:00Z</updated>
+              </entry>
+            </feed>"#;
+        assert_eq!(
+            parse_feed(atom, &base),
+            vec![
+                Seed {
+                    published_at_linux_epoch_secs: Some(1699833600),
+                    ..Seed::new(url("https://example.com/posts/two"))
+                },
+                Seed::new(url("https://example.com/posts/three")),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_what_feeds_say_about_entries() {
+        let base = url("https://example.com/feed.xml");
+        let rss = r#"<rss xmlns:dc="http://purl.org/dc/elements/1.1/"
+                          xmlns:content="http://purl.org/rss/1.0/modules/content/"><channel>
+              <item><title> One </title><link>/posts/one</link><dc:creator>Ada</dc:creator>
+                <description>Short.</description>
+                <content:encoded><![CDATA[<p>The whole post.</p>]]></content:encoded></item>
+              <item><title>No link</title></item>
+            </channel></rss>"#;
+        assert_eq!(
+            parse_feed_entries(rss, &base),
+            vec![
+                FeedEntry {
+                    seed: Some(Seed::new(url("https://example.com/posts/one"))),
+                    title: Some("One".to_string()),
+                    author: Some("Ada".to_string()),
+                    summary: Some("<p>The whole post.</p>".to_string()),
+                },
+                FeedEntry {
+                    title: Some("No link".to_string()),
+                    ..FeedEntry::default()
+                },
+            ]
+        );
+
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom"><entry>
+              <link href="/posts/two"/><author><name>Grace</name></author>
+              <summary>About two.</summary>
+            </entry></feed>"#;
+        let entries = parse_feed_entries(atom, &base);
+        assert_eq!(entries[0].author.as_deref(), Some("Grace"));
+        assert_eq!(entries[0].summary.as_deref(), Some("About two."));
+    }
+
+    #[test]
+    fn finds_advertised_feeds() {
+        let html = r#"<html><head>
+              <link rel="stylesheet" href="/style.css">
+              <link rel="alternate" type="application/rss+xml" href="/index.xml">
+              <link rel="alternate" type="application/atom+xml" href="https://example.com/atom.xml">
+            </head></html>"#;
+        assert_eq!(
+            feed_links(html, &url("https://example.com/blog/")),
+            vec![
+                url("https://example.com/index.xml"),
+                url("https://example.com/atom.xml")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn discovers_seeds_from_sitemaps_and_feeds() {
+        use axum::http::header;
+        use axum::routing::get;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://localhost:{}", listener.local_addr().unwrap().port());
+
+        let xml = |body: String| ([(header::CONTENT_TYPE, "application/xml")], body);
+        let (robots_txt, index, posts, feed) = (
+            format!("User-agent: *\nDisallow: /private\nSitemap: {}/sitemap-index.xml", base),
+            format!("<sitemapindex><sitemap><loc>{}/sitemap-posts.xml</loc></sitemap></sitemapindex>", base),
+            format!(
+                "<urlset><url><loc>{0}/blog/one</loc></url><url><loc>{0}/private/two</loc></url></urlset>",
+                base
+            ),
+            "<rss><channel><item><link>/blog/three</link><pubDate>Mon, 13 Nov 2023 00:00:00 GMT</pubDate></item>\
+             <item><link>/blog/one</link></item></channel></rss>",
+        );
+        let app = axum::Router::new()
+            .route("/robots.txt", get(move || async move { robots_txt }))
+            .route("/sitemap-index.xml", get(move || async move { xml(index) }))
+            .route("/sitemap-posts.xml", get(move || async move { xml(posts) }))
+            .route(
+                "/feed.xml",
+                get(move || async move { xml(feed.to_string()) }),
+            )
+            .route(
+                "/",
+                get(|| async {
+                    axum::response::Html(
+                        r#"<link rel="alternate" type="application/rss+xml" href="/feed.xml">"#,
+                    )
+                }),
+            );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let website = Website {
+            crawl_delay_ms: Some(0),
+            ..Website::new(&format!("{}/", base))
+        };
+        let seeds = discover(
+            &reqwest::Client::new(),
+            &Scheduler::new(4, std::slice::from_ref(&website)),
+            &RobotsCache::new("folklore"),
+            &website,
+            &ScopePolicy::new(std::slice::from_ref(&website)),
+        )
+        .await;
+
+        // The sitemap's /private page is still a seed: robots.txt is checked
+        // when the page itself is crawled.
+        assert_eq!(
+            seeds,
+            vec![
+                Seed::new(url(&format!("{}/blog/one", base))),
+                Seed::new(url(&format!("{}/private/two", base))),
+                Seed {
+                    published_at_linux_epoch_secs: Some(1699833600),
+                    ..Seed::new(url(&format!("{}/blog/three", base)))
+                },
+            ]
+        );
+    }
+}
@@ -1,44 +1,540 @@
 use regex::Regex;
 use select::document::Document;
-use select::predicate::Text;
+use select::node::Node;
+use select::predicate::{Attr, Name, Predicate};
+use serde_json::Value;
+use std::collections::HashMap;
 
-pub async fn resp_to_document(resp_body: String) -> Option<Document> {
-    Some(Document::from(resp_body.as_ref()))
+/// Elements that never hold content worth indexing, dropped with everything inside them.
+const DROPPED_TAGS: &[&str] = &[
+    "script", "style", "nav", "footer", "noscript", "template", "svg", "iframe", "head",
+];
+
+/// Elements that start a new block of text. Text never runs from one into another.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+const HEADING_TAGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// The elements whose text counts towards their ancestors' content score.
+const SCORED_TAGS: &[&str] = &["p", "pre", "td", "blockquote"];
+
+/// If the best-scoring candidate has less text than this, the page probably
+/// isn't an article (e.g. it's an index page), so we keep all of it.
+const MIN_ARTICLE_LEN: usize = 250;
+
+/// The readable text of a page, split into blocks.
+#[derive(Debug, Default, PartialEq)]
+pub struct Content {
+    /// The text of each heading in the main content, in document order.
+    pub headings: Vec<String>,
+
+    /// Every other block of text in the main content, in document order.
+    pub paragraphs: Vec<String>,
 }
 
-pub fn extract_texts(document: &Document) -> Vec<String> {
+/// What a page says about itself, beyond its text.
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    /// The `<title>`, or failing that the OpenGraph title or first `<h1>`.
+    pub title: Option<String>,
+
+    /// The meta (or OpenGraph) description.
+    pub description: Option<String>,
+
+    /// The author, from JSON-LD or the `author` / `article:author` meta tags.
+    pub author: Option<String>,
+
+    /// `datePublished` from JSON-LD, or the `article:published_time` meta tag.
+    pub published_at_linux_epoch_secs: Option<u64>,
+
+    /// The `<html lang>`, e.g. "en-US".
+    pub language: Option<String>,
+}
+
+pub fn extract_metadata(document: &Document) -> Metadata {
+    let json_ld: Vec<Value> = document
+        .find(Name("script").and(Attr("type", "application/ld+json")))
+        .filter_map(|node| serde_json::from_str(&node.text()).ok())
+        .flat_map(json_ld_items)
+        .collect();
+
+    let title = document
+        .find(Name("title"))
+        .next()
+        .map(|node| clean(&node.text()))
+        .or_else(|| meta(document, "property", "og:title"))
+        .filter(|title| !title.is_empty())
+        .or_else(|| {
+            document
+                .find(Name("h1"))
+                .next()
+                .map(|node| visible_text(node))
+        });
+
+    Metadata {
+        title,
+        description: meta(document, "name", "description")
+            .or_else(|| meta(document, "property", "og:description")),
+        author: json_ld
+            .iter()
+            .find_map(|item| item.get("author").and_then(author_name))
+            .or_else(|| meta(document, "name", "author"))
+            .or_else(|| meta(document, "property", "article:author")),
+        published_at_linux_epoch_secs: json_ld
+            .iter()
+            .find_map(|item| item.get("datePublished")?.as_str())
+            .map(str::to_string)
+            .or_else(|| meta(document, "property", "article:published_time"))
+            .and_then(|date| crate::discovery::parse_date(&date)),
+        language: document
+            .find(Name("html"))
+            .next()
+            .and_then(|html| html.attr("lang"))
+            .map(clean)
+            .filter(|lang| !lang.is_empty()),
+    }
+}
+
+/// The `content` of the first `<meta {attr}="{value}">`.
+fn meta(document: &Document, attr: &str, value: &str) -> Option<String> {
     document
-        .find(Text)
-        .into_iter()
-        .filter_map(|n| find_searchable_text(n.text()))
-        .collect()
+        .find(Name("meta").and(Attr(attr, value)))
+        .find_map(|node| node.attr("content"))
+        .map(clean)
+        .filter(|content| !content.is_empty())
+}
+
+/// The items of a JSON-LD block, which may be one item, a list of them, or a `@graph`.
+fn json_ld_items(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(items) => items.into_iter().flat_map(json_ld_items).collect(),
+        Value::Object(mut item) => match item.remove("@graph") {
+            Some(graph) => json_ld_items(graph),
+            None => vec![Value::Object(item)],
+        },
+        _ => vec![],
+    }
 }
 
-fn find_searchable_text(s: String) -> Option<String> {
-    let s = s.trim();
+/// A JSON-LD author is a name, a `Person` with a name, or a list of either.
+fn author_name(author: &Value) -> Option<String> {
+    match author {
+        Value::String(name) => Some(clean(name)),
+        Value::Object(person) => person.get("name").and_then(author_name),
+        Value::Array(authors) => {
+            let names: Vec<String> = authors.iter().filter_map(author_name).collect();
+            Some(names.join(", "))
+        }
+        _ => None,
+    }
+    .filter(|name| !name.is_empty())
+}
+
+fn clean(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The blocks of text in the page's main content, with headings and
+/// paragraphs kept apart. Each heading is a block of its own.
+pub fn extract_content(document: &Document) -> Content {
+    let mut content = Content::default();
+    for (is_heading, text) in blocks(document) {
+        if is_heading {
+            content.headings.push(text);
+        } else {
+            content.paragraphs.push(text);
+        }
+    }
+    content
+}
+
+/// The blocks of text in markdown (or plain text), each flagged with whether
+/// it's a heading. Blank lines, headings and code fences end a block, and the
+/// markup that starts a line (`#`, `>`, list markers) is dropped, except
+/// inside code fences, where lines are kept as they are.
+pub fn markdown_blocks(text: &str) -> Vec<(bool, String)> {
+    let mut blocks = vec![];
+    let mut block = String::new();
+    let mut in_code = false;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("```") {
+            flush(&mut block, false, &mut blocks);
+            in_code ^= line.starts_with("```");
+            continue;
+        }
+        if in_code {
+            block.push(' ');
+            block.push_str(line);
+            continue;
+        }
+        let heading = line.trim_start_matches('#');
+        if heading.len() < line.len() {
+            flush(&mut block, false, &mut blocks);
+            flush(&mut heading.to_string(), true, &mut blocks);
+            continue;
+        }
+
+        let line = line.trim_start_matches('>').trim_start();
+        let line = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|marker| line.strip_prefix(marker))
+            .unwrap_or(line);
+        block.push(' ');
+        block.push_str(line);
+    }
+    flush(&mut block, false, &mut blocks);
+    blocks
+}
+
+/// The main content's blocks of text, each flagged with whether it's a heading.
+fn blocks(document: &Document) -> Vec<(bool, String)> {
+    let mut blocks = vec![];
+    let mut text = String::new();
+    for node in main_content(document) {
+        collect_blocks(node, false, &mut text, &mut blocks);
+        flush(&mut text, false, &mut blocks);
+    }
+    blocks
+}
+
+/// Finds the page's main content, readability-style: paragraphs award points
+/// to their parent and grandparent, adjusted by what the elements' classes and
+/// ids suggest and by how much of their text is links. The best candidate wins,
+/// along with any siblings that look like part of the same article.
+fn main_content(document: &Document) -> Vec<Node> {
+    let root = match document.find(Name("body")).next() {
+        Some(body) => body,
+        None => match document.nth(0) {
+            Some(root) => root,
+            None => return vec![],
+        },
+    };
+
+    let mut scores: HashMap<usize, f32> = HashMap::new();
+    for paragraph in root
+        .descendants()
+        .filter(|n| n.name().map_or(false, |name| SCORED_TAGS.contains(&name)))
+    {
+        if is_dropped(paragraph) {
+            continue;
+        }
+        let text = visible_text(paragraph);
+        let text = text.trim();
+        if text.chars().count() < 25 {
+            continue;
+        }
+        let points =
+            1.0 + text.matches(',').count() as f32 + (text.chars().count() as f32 / 100.0).min(3.0);
+
+        let parent = paragraph.parent();
+        let grandparent = parent.and_then(|p| p.parent());
+        for (ancestor, share) in [(parent, 1.0), (grandparent, 0.5)] {
+            if let Some(ancestor) = ancestor.filter(|a| a.name().is_some()) {
+                *scores
+                    .entry(ancestor.index())
+                    .or_insert_with(|| initial_score(ancestor)) += points * share;
+            }
+        }
+    }
+
+    let score = |node: Node| {
+        scores
+            .get(&node.index())
+            .map(|s| s * (1.0 - link_density(node)))
+    };
+    let best = scores
+        .keys()
+        .filter_map(|&i| document.nth(i))
+        .filter_map(|node| Some((node, score(node)?)))
+        .max_by(|(a, a_score), (b, b_score)| {
+            a_score.total_cmp(b_score).then(b.index().cmp(&a.index()))
+        });
+
+    let (best, best_score) = match best {
+        Some((best, best_score)) if visible_text(best).trim().len() >= MIN_ARTICLE_LEN => {
+            (best, best_score)
+        }
+        _ => return vec![root],
+    };
 
+    // Articles are sometimes split across siblings, e.g. by an ad in the middle.
+    let threshold = (best_score * 0.2).max(10.0);
+    match best.parent() {
+        Some(parent) => parent
+            .children()
+            .filter(|sibling| {
+                sibling.index() == best.index()
+                    || score(*sibling).map_or(false, |s| s >= threshold)
+                    || (sibling.name() == Some("p")
+                        && visible_text(*sibling).trim().len() > 80
+                        && link_density(*sibling) < 0.25)
+            })
+            .collect(),
+        None => vec![best],
+    }
+}
+
+fn initial_score(node: Node) -> f32 {
     lazy_static! {
-        static ref HAS_WORDS: Regex = Regex::new("\\S").unwrap();
-        static ref IGNORE_PREFIXES: Vec<&'static str> = vec![
-            "body{",
-            "/*",
-            "if (",
-            ".has-",
-            ".widget",
-            "img.",
-            "{\"",
-            "window.",
-            "(function",
-            "@font",
-            "var ",
-            "MathJax.",
-            "<"
-        ];
-    }
-
-    if HAS_WORDS.is_match(&s) && IGNORE_PREFIXES.iter().all(|prefix| !s.starts_with(prefix)) {
-        Some(s.to_string())
-    } else {
-        None
+        static ref POSITIVE: Regex =
+            Regex::new("(?i)article|body|content|entry|hentry|main|page|post|text|blog|story").unwrap();
+        static ref NEGATIVE: Regex = Regex::new(
+            "(?i)banner|combx|comment|contact|cookie|foot|footer|footnote|masthead|menu|meta|modal|\
+             outbrain|popup|promo|related|share|shoutbox|sidebar|skyscraper|social|sponsor|widget"
+        )
+        .unwrap();
+    }
+
+    let tag_score = match node.name() {
+        Some("article") | Some("main") => 10.0,
+        Some("div") => 5.0,
+        Some("pre") | Some("td") | Some("blockquote") => 3.0,
+        Some("form") | Some("ol") | Some("ul") | Some("li") | Some("address") => -3.0,
+        Some("h1") | Some("h2") | Some("h3") | Some("h4") | Some("h5") | Some("h6")
+        | Some("th") => -5.0,
+        _ => 0.0,
+    };
+
+    let mut class_score = 0.0;
+    for name in node.attr("class").into_iter().chain(node.attr("id")) {
+        if NEGATIVE.is_match(name) {
+            class_score -= 25.0;
+        }
+        if POSITIVE.is_match(name) {
+            class_score += 25.0;
+        }
+    }
+
+    tag_score + class_score
+}
+
+/// The share of a node's text that sits inside links.
+fn link_density(node: Node) -> f32 {
+    let length = visible_text(node).len();
+    if length == 0 {
+        return 0.0;
+    }
+    let link_length: usize = node
+        .find(Name("a"))
+        .filter(|a| !is_dropped(*a))
+        .map(|a| visible_text(a).len())
+        .sum();
+    link_length as f32 / length as f32
+}
+
+fn is_dropped(node: Node) -> bool {
+    std::iter::successors(Some(node), |n| n.parent())
+        .any(|n| n.name().map_or(false, |name| DROPPED_TAGS.contains(&name)))
+}
+
+/// The text of a node, leaving out whatever's in dropped elements.
+fn visible_text(node: Node) -> String {
+    let mut text = String::new();
+    let mut blocks = vec![];
+    collect_blocks(node, false, &mut text, &mut blocks);
+    flush(&mut text, false, &mut blocks);
+    blocks
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn collect_blocks(
+    node: Node,
+    in_heading: bool,
+    text: &mut String,
+    blocks: &mut Vec<(bool, String)>,
+) {
+    let name = match node.name() {
+        Some(name) => name,
+        None => {
+            if let Some(t) = node.as_text() {
+                text.push_str(t);
+            }
+            return;
+        }
+    };
+
+    if DROPPED_TAGS.contains(&name) {
+        return;
+    }
+    if name == "br" {
+        text.push(' ');
+        return;
+    }
+
+    let is_block = BLOCK_TAGS.contains(&name);
+    let is_heading = in_heading || HEADING_TAGS.contains(&name);
+    if is_block {
+        flush(text, in_heading, blocks);
+    }
+    for child in node.children() {
+        collect_blocks(child, is_heading, text, blocks);
+    }
+    if is_block {
+        flush(text, is_heading, blocks);
+    }
+}
+
+/// Ends the block of text being collected, keeping it if it has any words.
+fn flush(text: &mut String, is_heading: bool, blocks: &mut Vec<(bool, String)>) {
+    let block = clean(text);
+    text.clear();
+    if block.chars().any(char::is_alphanumeric) {
+        blocks.push((is_heading, block));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = r#"<html>
+        <head><title>The tail at scale</title><style>body { color: red }</style></head>
+        <body>
+          <nav><a href="/">Home</a> <a href="/archive">Archive</a></nav>
+          <div class="cookie-banner">We use cookies, and so, by reading, you agree, to all of them.</div>
+          <div id="content" class="post">
+            <h1>The tail at <em>scale</em></h1>
+            <p>Most latency in a datacenter comes from the tail, not the median, because requests fan out.</p>
+            <script>window.analytics = { track: function() {} };</script>
+            <h2>Hedged requests</h2>
+            <p>Send the request twice, and use whichever answer comes back first, cancelling the other.</p>
+            <p>It costs a little extra load, but it cuts the p99 latency a lot, in practice, for most services.</p>
+          </div>
+          <div class="sidebar"><ul><li><a href="/a">Another post about caching, and more</a></li></ul></div>
+          <noscript>Please enable JavaScript.</noscript>
+          <footer>Copyright, all rights reserved, forever and ever, amen.</footer>
+        </body></html>"#;
+
+    #[test]
+    fn extracts_the_article_with_headings_apart() {
+        assert_eq!(
+            extract_content(&Document::from(ARTICLE)),
+            Content {
+                headings: vec!["The tail at scale".to_string(), "Hedged requests".to_string()],
+                paragraphs: vec![
+                    "Most latency in a datacenter comes from the tail, not the median, because requests fan out."
+                        .to_string(),
+                    "Send the request twice, and use whichever answer comes back first, cancelling the other."
+                        .to_string(),
+                    "It costs a little extra load, but it cuts the p99 latency a lot, in practice, for most services."
+                        .to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_the_whole_body_of_pages_without_an_article() {
+        let index = r#"<html><body>
+            <nav>Home</nav>
+            <h1>Posts</h1>
+            <ul><li><a href="/one">One</a></li><li><a href="/two">Two</a></li></ul>
+            <script>var x = 1;</script>
+        </body></html>"#;
+        assert_eq!(
+            extract_content(&Document::from(index)),
+            Content {
+                headings: vec!["Posts".to_string()],
+                paragraphs: vec!["One".to_string(), "Two".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn extracts_metadata() {
+        let page = r#"<html lang="en-US"><head>
+            <title>
+              The tail at scale
+            </title>
+            <meta name="description" content="Why p99s matter.">
+            <meta property="og:description" content="Ignored, as there's a plain description.">
+            <meta property="article:author" content="https://example.com/ignored">
+            <script type="application/ld+json">
+              {"@context": "https://schema.org", "@graph": [
+                {"@type": "WebSite", "name": "Example"},
+                {"@type": "BlogPosting", "datePublished": "2023-11-13T00:00:00Z",
+                 "author": [{"@type": "Person", "name": "Jeff Dean"}, "Luiz Barroso"]}
+              ]}
+            </script>
+          </head><body>
+            <h1>The tail at scale</h1>
+          </body></html>"#;
+
+        assert_eq!(
+            extract_metadata(&Document::from(page)),
+            Metadata {
+                title: Some("The tail at scale".to_string()),
+                description: Some("Why p99s matter.".to_string()),
+                author: Some("Jeff Dean, Luiz Barroso".to_string()),
+                published_at_linux_epoch_secs: Some(1699833600),
+                language: Some("en-US".to_string()),
+            }
+        );
+
+        let bare = r#"<html><head>
+            <meta property="og:description" content="An OpenGraph description.">
+            <meta property="article:published_time" content="2023-11-13">
+            <meta name="author" content="Dan Luu">
+          </head><body><h1>Untitled</h1></body></html>"#;
+        assert_eq!(
+            extract_metadata(&Document::from(bare)),
+            Metadata {
+                title: Some("Untitled".to_string()),
+                description: Some("An OpenGraph description.".to_string()),
+                author: Some("Dan Luu".to_string()),
+                published_at_linux_epoch_secs: Some(1699833600),
+                language: None,
+            }
+        );
+    }
+
+    #[test]
+    fn splits_markdown_into_blocks() {
+        let markdown = "# Folklore\n\nA search engine for\n*systems* folklore.\n## Usage\n- crawl\n- index\n```sh\ncargo run\n#include <stdio.h>\n```\n";
+        assert_eq!(
+            markdown_blocks(markdown),
+            [
+                (true, "Folklore".to_string()),
+                (false, "A search engine for *systems* folklore.".to_string()),
+                (true, "Usage".to_string()),
+                (false, "crawl index".to_string()),
+                (false, "cargo run #include <stdio.h>".to_string()),
+            ]
+        );
     }
 }
@@ -0,0 +1,417 @@
+use crate::Website;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+gflags::define! {
+    /// Where the crawler keeps its frontier and visited set, so that a killed
+    /// crawl can pick up where it left off.
+    pub --frontier_path <FRONTIER_PATH> = "/home/jmq/src/folklore.dev/frontier.jsonl"
+}
+
+/// A URL waiting to be crawled.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub url: String,
+
+    /// The `url` of the `Website` this URL was found through.
+    pub website: String,
+
+    /// How many links away from the website's root (or its sitemaps and
+    /// feeds) this URL is.
+    pub depth: u32,
+
+    /// Entries with a higher priority are crawled first. Ties go first-in,
+    /// first-out.
+    pub priority: i64,
+
+    pub published_at_linux_epoch_secs: Option<u64>,
+
+    /// Who wrote the page, and what it's about, as far as whatever listed it knows.
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Entry {
+    /// Adds what `other` knows about the page that this entry doesn't: its
+    /// publication date, author and tags. Returns whether anything was added.
+    fn merge(&mut self, other: Entry) -> bool {
+        let mut changed = false;
+        if self.published_at_linux_epoch_secs.is_none()
+            && other.published_at_linux_epoch_secs.is_some()
+        {
+            self.published_at_linux_epoch_secs = other.published_at_linux_epoch_secs;
+            changed = true;
+        }
+        if self.author.is_none() && other.author.is_some() {
+            self.author = other.author;
+            changed = true;
+        }
+        for tag in other.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// One line of the frontier's journal.
+#[derive(Serialize, Deserialize, Debug)]
+enum Record {
+    /// The URL was seen, and will never be queued again.
+    Visited(String),
+    /// The entry was queued (and its URL seen). A later record for the same
+    /// URL replaces it.
+    Queued(Entry),
+    /// The entry's crawl finished.
+    Done(String),
+    /// The URL was counted against the `max_pages` of the website (by `url`).
+    Page { website: String, url: String },
+}
+
+/// The queue of URLs still to crawl, and every URL ever queued, kept in memory
+/// and journaled to disk as it changes.
+///
+/// Entries stay in the journal until they're marked `done`, so ones that were
+/// mid-crawl when the process died are crawled again on the next `open`. So do
+/// the pages each website has spent of its budget, so resuming doesn't refill it.
+pub struct Frontier {
+    path: PathBuf,
+    journal: BufWriter<File>,
+    queue: BinaryHeap<(i64, Reverse<u64>)>,
+    entries: HashMap<u64, Entry>,
+    /// The sequence number of each queued entry, by URL.
+    queued: HashMap<String, u64>,
+    next_seq: u64,
+    in_flight: HashSet<String>,
+    visited: HashSet<String>,
+    pages: HashMap<String, HashSet<String>>,
+}
+
+impl Frontier {
+    /// Opens the frontier journaled at `path`, creating it if need be.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut pending: Vec<Entry> = vec![];
+        let mut pending_at: HashMap<String, usize> = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut pages: HashMap<String, HashSet<String>> = HashMap::new();
+
+        if path.exists() {
+            let mut done = HashSet::new();
+            for line in BufReader::new(File::open(path)?).lines() {
+                // The last line may be cut short if we were killed mid-write.
+                match serde_json::from_str(&line?) {
+                    Ok(Record::Visited(url)) => {
+                        visited.insert(url);
+                    }
+                    Ok(Record::Queued(entry)) => {
+                        visited.insert(entry.url.clone());
+                        done.remove(&entry.url);
+                        match pending_at.get(&entry.url) {
+                            Some(&i) => pending[i] = entry,
+                            None => {
+                                pending_at.insert(entry.url.clone(), pending.len());
+                                pending.push(entry);
+                            }
+                        }
+                    }
+                    Ok(Record::Done(url)) => {
+                        done.insert(url);
+                    }
+                    Ok(Record::Page { website, url }) => {
+                        pages.entry(website).or_default().insert(url);
+                    }
+                    Err(e) => eprintln!("Skipping a bad line in {}: {}", path.display(), e),
+                }
+            }
+            pending.retain(|entry| !done.contains(&entry.url));
+        }
+
+        let mut frontier = Frontier {
+            path: path.to_path_buf(),
+            journal: BufWriter::new(File::create(path.with_extension("tmp"))?),
+            queue: BinaryHeap::new(),
+            entries: HashMap::new(),
+            queued: HashMap::new(),
+            next_seq: 0,
+            in_flight: HashSet::new(),
+            visited: HashSet::new(),
+            pages: HashMap::new(),
+        };
+
+        // Rewrite the journal with just what's still live, so it doesn't grow forever.
+        for url in &visited {
+            frontier.append(&Record::Visited(url.clone()))?;
+        }
+        for entry in pending {
+            frontier.enqueue(entry)?;
+        }
+        for (website, urls) in &pages {
+            for url in urls {
+                frontier.append(&Record::Page {
+                    website: website.clone(),
+                    url: url.clone(),
+                })?;
+            }
+        }
+        frontier.visited = visited;
+        frontier.pages = pages;
+        frontier.journal.flush()?;
+        std::fs::rename(path.with_extension("tmp"), path)?;
+        frontier.journal = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+
+        Ok(frontier)
+    }
+
+    /// Queues `entry`, unless its URL has been seen before. Returns whether it was queued.
+    ///
+    /// If the URL is still waiting in the queue, whatever `entry` knows about
+    /// the page that the queued entry doesn't (see `Entry::merge`) is added to
+    /// it, so it doesn't matter which source listed the page first.
+    pub fn push(&mut self, entry: Entry) -> io::Result<bool> {
+        if self.visited.insert(entry.url.clone()) {
+            self.enqueue(entry)?;
+            self.journal.flush()?;
+            return Ok(true);
+        }
+
+        let seq = self.queued.get(&entry.url).copied();
+        if let Some(queued) = seq.and_then(|seq| self.entries.get_mut(&seq)) {
+            if queued.merge(entry) {
+                let record = Record::Queued(queued.clone());
+                self.append(&record)?;
+                self.journal.flush()?;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Marks `url` as seen without queuing it, e.g. because it redirected to a
+    /// page we already have. Returns whether it was new.
+    pub fn visit(&mut self, url: &str) -> io::Result<bool> {
+        if !self.visited.insert(url.to_string()) {
+            return Ok(false);
+        }
+        self.append(&Record::Visited(url.to_string()))?;
+        self.journal.flush()?;
+        Ok(true)
+    }
+
+    /// Whether `url` could still be counted against `website`'s `max_pages`,
+    /// with `pending` other pages of the website already being fetched.
+    pub fn has_page_budget(&self, website: &Website, url: &str, pending: usize) -> bool {
+        let pages = self.pages.get(&website.url);
+        pages.map_or(false, |pages| pages.contains(url))
+            || website.max_pages.map_or(true, |max_pages| {
+                pages.map_or(0, HashSet::len) + pending < max_pages
+            })
+    }
+
+    /// Counts `url` against `website`'s `max_pages`, returning false if the
+    /// budget is already spent. Pages counted before, even before the crawl
+    /// was resumed, are free.
+    pub fn take_page(&mut self, website: &Website, url: &str) -> io::Result<bool> {
+        let pages = self.pages.entry(website.url.clone()).or_default();
+        if pages.contains(url) {
+            return Ok(true);
+        }
+        if website
+            .max_pages
+            .map_or(false, |max_pages| pages.len() >= max_pages)
+        {
+            return Ok(false);
+        }
+        pages.insert(url.to_string());
+        self.append(&Record::Page {
+            website: website.url.clone(),
+            url: url.to_string(),
+        })?;
+        self.journal.flush()?;
+        Ok(true)
+    }
+
+    /// Takes the highest-priority entry off the queue. It stays in the journal
+    /// until it's marked `done`.
+    pub fn pop(&mut self) -> Option<Entry> {
+        let (_, Reverse(seq)) = self.queue.pop()?;
+        let entry = self.entries.remove(&seq)?;
+        self.queued.remove(&entry.url);
+        self.in_flight.insert(entry.url.clone());
+        Some(entry)
+    }
+
+    /// Records that the crawl of `url` finished.
+    pub fn done(&mut self, url: &str) -> io::Result<()> {
+        self.in_flight.remove(url);
+        self.append(&Record::Done(url.to_string()))?;
+        self.journal.flush()
+    }
+
+    /// Whether nothing is queued or being crawled, i.e. the crawl is over.
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Forgets everything, to start a new crawl from scratch.
+    pub fn reset(&mut self) -> io::Result<()> {
+        *self = Frontier {
+            path: self.path.clone(),
+            journal: BufWriter::new(File::create(&self.path)?),
+            queue: BinaryHeap::new(),
+            entries: HashMap::new(),
+            queued: HashMap::new(),
+            next_seq: 0,
+            in_flight: HashSet::new(),
+            visited: HashSet::new(),
+            pages: HashMap::new(),
+        };
+        Ok(())
+    }
+
+    fn enqueue(&mut self, entry: Entry) -> io::Result<()> {
+        self.append(&Record::Queued(entry.clone()))?;
+        self.queue.push((entry.priority, Reverse(self.next_seq)));
+        self.queued.insert(entry.url.clone(), self.next_seq);
+        self.entries.insert(self.next_seq, entry);
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.journal, record)?;
+        self.journal.write_all(b"\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, priority: i64) -> Entry {
+        Entry {
+            url: url.to_string(),
+            website: "https://example.com".to_string(),
+            depth: 0,
+            priority,
+            published_at_linux_epoch_secs: None,
+            author: None,
+            tags: vec![],
+        }
+    }
+
+    fn urls(frontier: &mut Frontier) -> Vec<String> {
+        std::iter::from_fn(|| frontier.pop())
+            .map(|e| e.url)
+            .collect()
+    }
+
+    #[test]
+    fn pops_by_priority_then_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut frontier = Frontier::open(&dir.path().join("frontier.jsonl")).unwrap();
+
+        for (url, priority) in [("a", 0), ("b", 1), ("c", 0), ("a", 5), ("d", 1)] {
+            frontier.push(entry(url, priority)).unwrap();
+        }
+        assert!(!frontier.visit("a").unwrap());
+        assert!(frontier.visit("e").unwrap());
+        assert!(!frontier.push(entry("e", 9)).unwrap());
+
+        assert_eq!(urls(&mut frontier), vec!["b", "d", "a", "c"]);
+        assert!(!frontier.is_finished());
+    }
+
+    #[test]
+    fn resumes_where_it_left_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frontier.jsonl");
+
+        {
+            let mut frontier = Frontier::open(&path).unwrap();
+            for url in ["a", "b", "c"] {
+                frontier.push(entry(url, 0)).unwrap();
+            }
+            frontier.visit("alias").unwrap();
+
+            let a = frontier.pop().unwrap();
+            frontier.done(&a.url).unwrap();
+            // "b" is mid-crawl when we're killed.
+            frontier.pop().unwrap();
+        }
+
+        // Being killed mid-write leaves half a line behind.
+        let mut journal = OpenOptions::new().append(true).open(&path).unwrap();
+        journal.write_all(b"{\"Queued\":{\"url\":\"d").unwrap();
+
+        let mut frontier = Frontier::open(&path).unwrap();
+        assert!(!frontier.push(entry("a", 0)).unwrap());
+        assert!(!frontier.visit("alias").unwrap());
+        assert_eq!(urls(&mut frontier), vec!["b", "c"]);
+
+        frontier.done("b").unwrap();
+        frontier.done("c").unwrap();
+        assert!(frontier.is_finished());
+
+        frontier.reset().unwrap();
+        assert!(frontier.push(entry("a", 0)).unwrap());
+        assert_eq!(Frontier::open(&path).unwrap().pop(), Some(entry("a", 0)));
+    }
+
+    #[test]
+    fn merges_what_later_listings_know_about_queued_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frontier.jsonl");
+        let listed = Entry {
+            published_at_linux_epoch_secs: Some(1_600_000_000),
+            author: Some("Marc Brooker".to_string()),
+            tags: vec!["Architecture".to_string()],
+            ..entry("a", 0)
+        };
+
+        {
+            let mut frontier = Frontier::open(&path).unwrap();
+            // The website's sitemap listed the page before the library did.
+            assert!(frontier.push(entry("a", 0)).unwrap());
+            assert!(!frontier.push(listed.clone()).unwrap());
+            assert!(!frontier.push(entry("a", 0)).unwrap());
+        }
+
+        let mut frontier = Frontier::open(&path).unwrap();
+        assert_eq!(frontier.pop(), Some(listed));
+        assert_eq!(frontier.pop(), None);
+    }
+
+    #[test]
+    fn page_budgets_survive_resuming() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frontier.jsonl");
+        let website = Website {
+            max_pages: Some(2),
+            ..Website::new("https://budget.example")
+        };
+        let page = |path: &str| format!("https://budget.example{}", path);
+
+        {
+            let mut frontier = Frontier::open(&path).unwrap();
+            assert!(frontier.take_page(&website, &page("/")).unwrap());
+            assert!(frontier.take_page(&website, &page("/a")).unwrap());
+            assert!(frontier.take_page(&website, &page("/")).unwrap());
+        }
+
+        let mut frontier = Frontier::open(&path).unwrap();
+        assert!(frontier.has_page_budget(&website, &page("/a"), 1));
+        assert!(!frontier.has_page_budget(&website, &page("/b"), 0));
+        assert!(frontier.take_page(&website, &page("/a")).unwrap());
+        assert!(!frontier.take_page(&website, &page("/b")).unwrap());
+
+        frontier.reset().unwrap();
+        assert!(frontier.take_page(&website, &page("/b")).unwrap());
+    }
+}
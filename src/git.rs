@@ -0,0 +1,226 @@
+use crate::net::{self, SearchableDocument};
+use crate::Repository;
+use std::io;
+use std::process::Command;
+use url::Url;
+
+/// Files with these extensions (or named README) are read as documents.
+pub const DOC_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "txt", "text", "rst", "adoc"];
+
+/// Bigger files are generated or vendored, not written by someone to be read.
+pub const MAX_DOC_BYTES: usize = 1 << 20;
+
+/// Separates the fields, and ends the records, of the `git log` we ask for.
+const FIELD_SEPARATOR: char = '\u{1f}';
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// Turns a repository's commit messages, and the markdown and text files at
+/// its HEAD, into documents.
+///
+/// Commits live at `{url}/commit/{hash}` and files at `{url}/blob/HEAD/{path}`,
+/// so a document keeps its URL however many times the repository is ingested.
+pub fn ingest(repository: &Repository) -> io::Result<Vec<SearchableDocument>> {
+    let base = repository.base_url()?;
+    if git(repository, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_err() {
+        println!("{} has no commits yet.", repository.path.display());
+        return Ok(vec![]);
+    }
+
+    let mut documents = commits(repository, &base)?;
+    documents.extend(files(repository, &base)?);
+    Ok(documents)
+}
+
+fn commits(repository: &Repository, base: &Url) -> io::Result<Vec<SearchableDocument>> {
+    let format = format!(
+        "--format=%H{0}%an{0}%at{0}%B{1}",
+        FIELD_SEPARATOR, RECORD_SEPARATOR
+    );
+    let log = String::from_utf8_lossy(&git(repository, &["log", &format, "HEAD"])?).into_owned();
+
+    Ok(log
+        .split(RECORD_SEPARATOR)
+        .filter_map(|record| {
+            let mut fields = record.trim_start().splitn(4, FIELD_SEPARATOR);
+            let hash = fields.next().filter(|hash| !hash.is_empty())?;
+            let author = fields.next()?;
+            let committed_at = fields.next()?.parse().ok();
+            let message = fields.next()?;
+
+            let mut document = net::text_document(message, &join(base, &["commit", hash]), hash);
+            document.title = message.lines().next().unwrap_or(hash).trim().to_string();
+            // A commit message isn't markdown, so a `#` line isn't a heading.
+            let headings = std::mem::take(&mut document.headings);
+            document.searchable_texts.extend(headings);
+            document.searchable_texts.push(hash.to_string());
+            document.author = Some(author.to_string()).filter(|author| !author.is_empty());
+            document.published_at_linux_epoch_secs = committed_at;
+            Some(document)
+        })
+        .collect())
+}
+
+fn files(repository: &Repository, base: &Url) -> io::Result<Vec<SearchableDocument>> {
+    let listing = git(repository, &["ls-tree", "-r", "-z", "--name-only", "HEAD"])?;
+    let paths = listing
+        .split(|&byte| byte == 0)
+        .filter_map(|path| std::str::from_utf8(path).ok())
+        .filter(|path| is_doc(path));
+
+    let mut documents = vec![];
+    for path in paths {
+        let contents = git(repository, &["show", &format!("HEAD:{}", path)])?;
+        if contents.len() > MAX_DOC_BYTES {
+            continue;
+        }
+        let text = match String::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+
+        let mut segments = vec!["blob", "HEAD"];
+        segments.extend(path.split('/'));
+        documents.push(net::text_document(&text, &join(base, &segments), path));
+    }
+    Ok(documents)
+}
+
+/// Whether the file at `path` is prose, by its extension or name.
+pub fn is_doc(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((_, extension)) => DOC_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()),
+        None => name.eq_ignore_ascii_case("readme"),
+    }
+}
+
+fn join(base: &Url, segments: &[&str]) -> Url {
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .expect("repository URLs have a path")
+        .pop_if_empty()
+        .extend(segments);
+    url
+}
+
+/// Runs git in the repository, returning its stdout.
+fn git(repository: &Repository, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&repository.path)
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "git {} in {}: {}",
+                args.join(" "),
+                repository.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn commit(dir: &Path, files: &[(&str, &str)], message: &str, date: &str) {
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        for args in [&["add", "-A"][..], &["commit", "-q", "-m", message]] {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=Ada", "-c", "user.email=ada@example.com"])
+                .args(args)
+                .env("GIT_AUTHOR_DATE", date)
+                .env("GIT_COMMITTER_DATE", date)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+    }
+
+    #[test]
+    fn ingests_commits_and_docs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = Repository {
+            path: dir.path().to_path_buf(),
+            url: Some("https://github.com/jmqd/folklore.dev/".to_string()),
+        };
+        assert!(Command::new("git")
+            .arg("init")
+            .arg("-q")
+            .arg(dir.path())
+            .status()
+            .unwrap()
+            .success());
+        assert!(ingest(&repository).unwrap().is_empty());
+
+        commit(
+            dir.path(),
+            &[
+                ("README.md", "# Folklore\n\nA search engine for\n*systems* folklore.\n\n## Usage\n\n- crawl\n"),
+                ("docs/design notes.txt", "Why we crawl breadth-first."),
+                ("src/main.rs", "fn main() {}"),
+            ],
+            "Add a README\n\nExplains what folklore is.",
+            "1700000000 +0000",
+        );
+        commit(
+            dir.path(),
+            &[("README.md", "# Folklore\n")],
+            "Trim the README",
+            "1700000100 +0000",
+        );
+
+        let documents = ingest(&repository).unwrap();
+        let urls: Vec<&str> = documents.iter().map(|d| d.url.as_str()).collect();
+        assert_eq!(urls.len(), 4);
+        assert!(urls[0].starts_with("https://github.com/jmqd/folklore.dev/commit/"));
+        assert_eq!(
+            &urls[2..],
+            [
+                "https://github.com/jmqd/folklore.dev/blob/HEAD/README.md",
+                "https://github.com/jmqd/folklore.dev/blob/HEAD/docs/design%20notes.txt",
+            ]
+        );
+
+        let first_commit = &documents[1];
+        assert_eq!(first_commit.title, "Add a README");
+        assert_eq!(first_commit.author.as_deref(), Some("Ada"));
+        assert_eq!(first_commit.published_at_linux_epoch_secs, Some(1700000000));
+        assert_eq!(
+            first_commit.searchable_texts[..2],
+            ["Add a README", "Explains what folklore is."]
+        );
+        assert!(first_commit
+            .url
+            .ends_with(&first_commit.searchable_texts[2]));
+
+        assert_eq!(documents[2].title, "Folklore");
+        assert_eq!(documents[3].title, "docs/design notes.txt");
+        assert_eq!(
+            documents[3].searchable_texts,
+            ["Why we crawl breadth-first."]
+        );
+
+        // Ingesting again gives every record the same URL.
+        assert_eq!(
+            ingest(&repository)
+                .unwrap()
+                .iter()
+                .map(|d| &d.url)
+                .collect::<Vec<_>>(),
+            documents.iter().map(|d| &d.url).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,28 +1,36 @@
-use crate::net;
-use crate::Website;
+use crate::analysis::{Analyzer, StandardAnalyzer};
+use crate::dedup;
+use crate::net::SearchableDocument;
+use crate::postings::PostingList;
+use crate::{is_under, Website};
 use bimap::BiMap;
-use futures::future;
-use itertools::Itertools;
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use std::time;
-use tokio::task;
+use std::ops::RangeBounds;
+use std::path::Path;
 use url::Url;
 
 /// An Index holds all state necessary to answer search queries.
 ///
-/// This index is backed by multiple inverted indexes, one for unigrams, and
-/// another for arbitrary length ngrams. These inverted indexes encode all
-/// tokens (e.g. words) and documents as u32 integers, translating to and from
-/// strings only at the interface boundary. (e.g. at query time.)
+/// This index is backed by a positional inverted index: for every unigram, it
+/// records each document the unigram appears in, and at which positions. An
+/// exact ngram (phrase) matches wherever its words sit at consecutive
+/// positions. The index encodes all tokens (e.g. words) and documents as u32
+/// integers, translating to and from strings only at the interface boundary.
+/// (e.g. at query time.)
 ///
-/// The index normalizes all tokens to lowercase. Tokens are identified by
-/// intervening whitespace. Different nodes in HTML documents isolate grams
-/// from each other. For example, a gram cannot span from one paragraph or
-/// div tag into another.
+/// Texts are turned into tokens by the index's `analyzer`, which queries are
+/// parsed with too, so both sides always agree on what a token is. (By
+/// default: lowercased, stemmed English words without stop words.) Different
+/// nodes in HTML documents isolate grams from each other. For example, a gram
+/// cannot span from one paragraph or div tag into another: every text node is
+/// followed by an unused position, so the last word of one node is never
+/// adjacent to the first of the next.
 ///
-/// In the unigram and ngram index data structures, we don't actually store the
+/// In the inverted index data structures, we don't actually store the
 /// words and URLs there. That would consume much more memory. Instead, we
 /// assign each word and URL a unique u32 number, then map everything in the
 /// indexes to these u32 integers. How to translate between the u32 integer and
@@ -30,18 +38,51 @@ use url::Url;
 /// members. At query time, we translate everything to numbers, perform the
 /// search, then at the last moment, after finding all the matches, we translate
 /// the results back to Strings for the user.
+#[derive(Default, Serialize, Deserialize)]
 pub struct Index {
-    /// A mapping from all words to all documents those words appear in.
-    pub unigrams: HashMap<u32, HashSet<u32>>,
-
-    /// A mapping from all ngrams to all documents those ngrams appear in.
-    pub ngrams: HashMap<Vec<u32>, HashSet<u32>>,
+    /// A mapping from all words to all documents those words appear in, and
+    /// the positions at which the word occurs in each of them.
+    pub unigrams: HashMap<u32, PostingList>,
 
     /// A bi-mapping from document_ids (e.g. URL strings) to its integer code.
     pub document_codes: BiMap<String, u32>,
 
     /// A bi-mapping from words to thier integer code.
     pub word_codes: BiMap<String, u32>,
+
+    /// The number of tokens in each document, indexed by document code.
+    pub document_lengths: Vec<u32>,
+
+    /// A mapping from words to the documents whose title contains them.
+    pub title_unigrams: HashMap<u32, RoaringBitmap>,
+
+    /// A mapping from each host (see `host_key`) to the documents hosted there.
+    pub hosts: BTreeMap<String, RoaringBitmap>,
+
+    /// A mapping from words to the documents whose author's name contains them.
+    pub author_unigrams: HashMap<u32, RoaringBitmap>,
+
+    /// A mapping from each tag, lowercased, to the documents tagged with it.
+    pub tags: BTreeMap<String, RoaringBitmap>,
+
+    /// When each document was published, in seconds since the Unix epoch, by
+    /// document code. Documents we have no date for are left out.
+    pub published_at: HashMap<u32, u64>,
+
+    /// How texts and queries are split into tokens.
+    pub analyzer: StandardAnalyzer,
+
+    /// The SimHash of each document's texts, by document code.
+    pub simhashes: HashMap<u32, u64>,
+
+    /// Documents left out of search results, because a near-duplicate of
+    /// theirs (see `collapse_duplicates`) is shown instead.
+    pub duplicates: RoaringBitmap,
+
+    /// The position each document's next text starts at. Only needed while
+    /// documents are being indexed, so it isn't saved in snapshots.
+    #[serde(skip)]
+    next_positions: HashMap<u32, u32>,
 }
 
 impl Index {
@@ -66,91 +107,340 @@ impl Index {
         }
     }
 
-    pub fn index_texts(&mut self, document_id: String, texts: HashSet<Vec<String>>) {
-        println!("Indexing document {}", document_id);
-        let document_code = self.get_or_generate_document_code(document_id);
+    /// Indexes the title and every searchable text of a crawled document.
+    /// Each text is tokenized separately, so grams never span two HTML nodes.
+    /// Its author and tags are searchable words too, as well as fields that
+    /// queries can filter on.
+    pub fn index_document(&mut self, document: SearchableDocument) {
+        let texts = std::iter::once(&document.title)
+            .chain(document.author.iter())
+            .chain(document.tags.iter())
+            .chain(document.headings.iter())
+            .chain(document.searchable_texts.iter())
+            .map(|text| self.analyzer.tokens(text))
+            .filter(|tokens| !tokens.is_empty())
+            .collect();
+
+        let document_code = self.get_or_generate_document_code(document.url.clone());
+        if let Some(host) = Url::parse(&document.url)
+            .ok()
+            .as_ref()
+            .and_then(Url::host_str)
+        {
+            self.hosts
+                .entry(host_key(host))
+                .or_default()
+                .insert(document_code);
+        }
 
-        for ngram in texts.into_iter() {
-            for unigram in ngram.clone().into_iter() {
-                self.insert_unigram(unigram, document_code);
-            }
+        if let Some(simhash) = dedup::simhash(&document.searchable_texts) {
+            self.simhashes.insert(document_code, simhash);
+        }
+
+        for word in self.analyzer.analyze(&document.title) {
+            let code = self.get_or_generate_word_code(word);
+            self.title_unigrams
+                .entry(code)
+                .or_default()
+                .insert(document_code);
+        }
+
+        let author_words = (document.author.as_deref())
+            .map(|author| self.analyzer.analyze(author))
+            .unwrap_or_default();
+        for word in author_words {
+            let code = self.get_or_generate_word_code(word);
+            self.author_unigrams
+                .entry(code)
+                .or_default()
+                .insert(document_code);
+        }
+
+        for tag in &document.tags {
+            self.tags
+                .entry(tag.trim().to_lowercase())
+                .or_default()
+                .insert(document_code);
+        }
+
+        if let Some(published_at) = document.published_at_linux_epoch_secs {
+            self.published_at.insert(document_code, published_at);
+        }
+
+        self.index_tokens(document.url, texts);
+    }
 
-            for bigram in ngram.clone().into_iter().tuple_windows::<(_, _)>() {
-                let bigram_vec = vec![bigram.0, bigram.1];
-                self.insert_ngram(bigram_vec, document_code);
+    /// Indexes a document's texts, already tokenized and in the order they
+    /// appear. A text repeated on the page counts every time, as BM25 expects
+    /// of term frequency and length.
+    pub fn index_texts(&mut self, document_id: String, texts: Vec<Vec<String>>) {
+        let texts = texts
+            .into_iter()
+            .map(|words| (0..).zip(words).collect())
+            .collect();
+        self.index_tokens(document_id, texts);
+    }
+
+    /// Like `index_texts`, but with each word's offset within its text (see
+    /// `Analyzer::tokens`), so dropped words keep their place.
+    pub fn index_tokens(&mut self, document_id: String, texts: Vec<Vec<(u32, String)>>) {
+        let document_code = self.get_or_generate_document_code(document_id);
+        if self.document_lengths.len() <= document_code as usize {
+            self.document_lengths.resize(document_code as usize + 1, 0);
+        }
+
+        // Every non-empty text node is followed by a one position gap, so the
+        // last word of one node is never adjacent to the first of the next.
+        let mut start = self
+            .next_positions
+            .get(&document_code)
+            .copied()
+            .unwrap_or(0);
+        for tokens in texts.into_iter().filter(|tokens| !tokens.is_empty()) {
+            self.document_lengths[document_code as usize] += tokens.len() as u32;
+
+            let mut end = start;
+            for (offset, unigram) in tokens {
+                self.insert_unigram(unigram, document_code, start + offset);
+                end = start + offset + 1;
             }
+            start = end + 1;
         }
+        self.next_positions.insert(document_code, start);
     }
 
-    pub fn insert_unigram(&mut self, unigram: String, document_code: u32) {
+    pub fn insert_unigram(&mut self, unigram: String, document_code: u32, position: u32) {
         let code = self.get_or_generate_word_code(unigram);
 
-        if self.unigrams.contains_key(&code) {
-            self.unigrams.get_mut(&code).unwrap().insert(document_code);
-        } else {
-            let mut set = HashSet::with_capacity(1);
-            set.insert(document_code);
-            self.unigrams.insert(code, set);
-        }
+        self.unigrams
+            .entry(code)
+            .or_default()
+            .push(document_code, position);
+    }
+
+    /// The documents containing `unigram`, with its positions in each.
+    pub fn unigram_postings(&self, unigram: &str) -> Option<&PostingList> {
+        self.unigrams.get(self.word_codes.get_by_left(unigram)?)
     }
 
-    pub fn insert_ngram(&mut self, ngram: Vec<String>, document_code: u32) {
-        let ngram_codes = ngram
+    /// The documents containing every word of `ngram` at consecutive positions.
+    pub fn ngram_documents(&self, ngram: &[String]) -> RoaringBitmap {
+        let phrase: Vec<(u32, String)> = (0..).zip(ngram.iter().cloned()).collect();
+        self.phrase_documents(&phrase)
+    }
+
+    /// The documents containing every word of `phrase` at the same distances
+    /// from each other as their offsets (see `Analyzer::tokens`).
+    pub fn phrase_documents(&self, phrase: &[(u32, String)]) -> RoaringBitmap {
+        let postings = match phrase
+            .iter()
+            .map(|(_, w)| self.unigram_postings(w))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(postings) if !postings.is_empty() => postings,
+            _ => return RoaringBitmap::new(),
+        };
+
+        // Only documents containing every word can contain the phrase. We then
+        // merge through the candidates in order, checking the words' relative positions.
+        let candidates = postings[1..]
+            .iter()
+            .fold(postings[0].documents().clone(), |c, p| c & p.documents());
+        let mut cursors: Vec<_> = postings.iter().map(|p| p.cursor()).collect();
+
+        candidates
             .into_iter()
-            .map(|w| self.get_or_generate_word_code(w))
-            .collect::<Vec<u32>>();
+            .filter(|document| {
+                let mut positions = cursors.iter_mut().map(|c| c.seek(*document));
+                let starts = match positions.next().flatten() {
+                    Some(starts) => starts,
+                    None => return false,
+                };
+                let rest = match positions
+                    .map(|p| p.map(|p| p.collect::<Vec<u32>>()))
+                    .collect::<Option<Vec<_>>>()
+                {
+                    Some(rest) => rest,
+                    None => return false,
+                };
+
+                let first = phrase[0].0;
+                starts.into_iter().any(|start| {
+                    rest.iter()
+                        .zip(&phrase[1..])
+                        .all(|(positions, (offset, _))| {
+                            positions.binary_search(&(start + offset - first)).is_ok()
+                        })
+                })
+            })
+            .collect()
+    }
 
-        if self.ngrams.contains_key(&ngram_codes) {
-            self.ngrams
-                .get_mut(&ngram_codes)
-                .unwrap()
-                .insert(document_code);
-        } else {
-            let mut set = HashSet::with_capacity(1);
-            set.insert(document_code);
-            self.ngrams.insert(ngram_codes, set);
+    /// The documents whose title contains every one of `words`.
+    pub fn title_documents(&self, words: &[String]) -> RoaringBitmap {
+        self.field_documents(&self.title_unigrams, words)
+    }
+
+    /// The documents whose author's name contains every one of `words`.
+    pub fn author_documents(&self, words: &[String]) -> RoaringBitmap {
+        self.field_documents(&self.author_unigrams, words)
+    }
+
+    /// The documents tagged with `tag`, whatever its case.
+    pub fn tag_documents(&self, tag: &str) -> RoaringBitmap {
+        self.tags
+            .get(&tag.trim().to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The documents published within `range`, in seconds since the Unix epoch.
+    pub fn published_documents(&self, range: impl RangeBounds<u64>) -> RoaringBitmap {
+        self.published_at
+            .iter()
+            .filter(|(_, published_at)| range.contains(published_at))
+            .map(|(document, _)| *document)
+            .collect()
+    }
+
+    /// When the document at `url` was published, if we know.
+    pub fn published_at(&self, url: &str) -> Option<u64> {
+        let code = self.document_codes.get_by_left(url)?;
+        self.published_at.get(code).copied()
+    }
+
+    /// The documents with every one of `words` in `field`, a mapping from
+    /// words to documents like `title_unigrams`.
+    fn field_documents(
+        &self,
+        field: &HashMap<u32, RoaringBitmap>,
+        words: &[String],
+    ) -> RoaringBitmap {
+        let mut documents: Option<RoaringBitmap> = None;
+        for word in words {
+            let matches = match self
+                .word_codes
+                .get_by_left(word)
+                .and_then(|code| field.get(code))
+            {
+                Some(matches) => matches,
+                None => return RoaringBitmap::new(),
+            };
+
+            documents = Some(match documents {
+                None => matches.clone(),
+                Some(d) => d & matches,
+            });
         }
+
+        documents.unwrap_or_default()
     }
 
-    pub fn unigram_match(&self, unigram: String) -> Option<HashSet<String>> {
-        match self.word_codes.get_by_left(&unigram) {
-            Some(code) => self.pass_page_results(self.unigrams.get(code)),
-            None => None,
+    /// The documents hosted on `host`, or on one of its subdomains.
+    pub fn site_documents(&self, host: &str) -> RoaringBitmap {
+        let host = host_key(host);
+        let subdomain_suffix = format!(".{}", host);
+
+        self.hosts
+            .iter()
+            .filter(|(h, _)| **h == host || h.ends_with(&subdomain_suffix))
+            .map(|(_, documents)| documents)
+            .fold(RoaringBitmap::new(), |all, documents| all | documents)
+    }
+
+    /// The documents that belong to `website`: those on its host, and under
+    /// its path, if it has one. (e.g. `http://dtrace.org/blogs/ahl`)
+    pub fn website_documents(&self, website: &Website) -> RoaringBitmap {
+        let url = match Url::parse(&website.url) {
+            Ok(url) => url,
+            Err(_) => return RoaringBitmap::new(),
+        };
+
+        let documents = match url.host_str().and_then(|h| self.hosts.get(&host_key(h))) {
+            Some(documents) => documents,
+            None => return RoaringBitmap::new(),
+        };
+
+        let path_prefix = url.path().trim_end_matches('/');
+        if path_prefix.is_empty() {
+            return documents.clone();
         }
+
+        documents
+            .iter()
+            .filter(|d| {
+                Url::parse(self.document_url(*d)).map_or(false, |u| is_under(u.path(), path_prefix))
+            })
+            .collect()
     }
 
-    pub fn ngram_match(&self, ngram: Vec<String>) -> Option<HashSet<String>> {
-        let ngram_codes = ngram
-            .into_iter()
-            .map(|w| self.word_codes.get_by_left(&w))
-            .collect::<Vec<Option<&u32>>>();
+    /// How many documents the index holds for each of `websites`.
+    pub fn website_document_counts<'w>(&self, websites: &'w [Website]) -> Vec<(&'w Website, u64)> {
+        websites
+            .iter()
+            .map(|w| (w, self.website_documents(w).len()))
+            .collect()
+    }
 
-        if ngram_codes.iter().any(|c| c.is_none()) {
-            return None;
+    /// Hides all but one document of each cluster of near-duplicates, such as
+    /// mirrors or the same post reachable under several URLs. The document
+    /// with the shortest URL is kept, as it's most likely the canonical one.
+    pub fn collapse_duplicates(&mut self) {
+        let hashes: Vec<(u32, u64)> = self.simhashes.iter().map(|(&d, &h)| (d, h)).collect();
+        self.duplicates.clear();
+
+        for cluster in dedup::near_duplicates(&hashes) {
+            let kept = cluster
+                .iter()
+                .copied()
+                .min_by_key(|&d| (self.document_url(d).len(), self.document_url(d)))
+                .expect("clusters are never empty");
+            println!(
+                "Collapsing {} near-duplicates of {}",
+                cluster.len() - 1,
+                self.document_url(kept)
+            );
+            self.duplicates
+                .extend(cluster.into_iter().filter(|&d| d != kept));
         }
+    }
 
-        let ngram_codes: Vec<u32> = ngram_codes.into_iter().map(|c| *c.unwrap()).collect();
+    pub fn all_documents(&self) -> RoaringBitmap {
+        let mut documents = RoaringBitmap::new();
+        documents.insert_range(0..self.document_codes.len() as u32);
+        documents
+    }
+
+    pub fn unigram_match(&self, unigram: String) -> Option<HashSet<String>> {
+        self.pass_page_results(self.unigram_postings(&unigram).map(|p| p.documents()))
+    }
 
-        self.pass_page_results(self.ngrams.get(&ngram_codes))
+    pub fn ngram_match(&self, ngram: Vec<String>) -> Option<HashSet<String>> {
+        let documents = self.ngram_documents(&ngram);
+        if documents.is_empty() {
+            None
+        } else {
+            self.pass_page_results(Some(documents))
+        }
     }
 
     pub fn pass_page_results(
         &self,
-        page_results: Option<&HashSet<u32>>,
+        page_results: Option<impl IntoIterator<Item = u32>>,
     ) -> Option<HashSet<String>> {
-        match page_results {
-            // If we found some pages that matches the search query:
-            // We copy all the page URLs into a return value for the caller.
-            Some(page_results) => Some(
-                page_results
-                    .into_iter()
-                    .map(|p| self.document_codes.get_by_right(p).unwrap().to_string())
-                    .collect(),
-            ),
+        // If we found some pages that matches the search query, we copy all the
+        // page URLs into a return value for the caller. Otherwise, their search
+        // query had no results.
+        page_results.map(|page_results| {
+            page_results
+                .into_iter()
+                .map(|p| self.document_url(p).to_string())
+                .collect()
+        })
+    }
 
-            // Otherwise, their search query had no results.
-            None => None,
-        }
+    pub fn document_url(&self, document_code: u32) -> &str {
+        self.document_codes.get_by_right(&document_code).unwrap()
     }
 
     pub fn exact_ngram_match(&self, ngram: Vec<String>) -> Option<HashSet<String>> {
@@ -161,74 +451,204 @@ impl Index {
     }
 }
 
-pub async fn build_index<'i>(websites: &'i Vec<Website>, db: Arc<ConnPool>) -> Arc<Mutex<Index>> {
-    let index = Arc::new(Mutex::new(Index {
-        unigrams: HashMap::new(),
-        ngrams: HashMap::new(),
-        document_codes: BiMap::new(),
-        word_codes: BiMap::new(),
-    }));
+/// Builds an Index from every `SearchableDocument` that `net::crawl` has
+/// written into `output_dir`.
+///
+/// Files that fail to deserialize are reported and skipped, so one corrupt
+/// document doesn't prevent the rest of the corpus from being searchable.
+pub fn build_index(output_dir: &Path, analyzer: StandardAnalyzer) -> std::io::Result<Index> {
+    let mut index = Index {
+        analyzer,
+        ..Index::default()
+    };
+
+    for entry in std::fs::read_dir(output_dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
 
-    loop {
-        let mut crawl_guard = crawl_stack.lock().unwrap();
-        let crawl_envelope = crawl_guard.pop();
-        std::mem::drop(crawl_guard);
-        if crawl_envelope.is_none() {
-            break;
+        match serde_json::from_str::<SearchableDocument>(&std::fs::read_to_string(&path)?) {
+            Ok(document) => index.index_document(document),
+            Err(err) => {
+                eprintln!("Failed to demarshal {}", path.display());
+                eprintln!("{:?}", err);
+            }
         }
+    }
 
-        let crawl_envelope = crawl_envelope.unwrap();
+    index.collapse_duplicates();
+    shrink_index(&mut index);
+    Ok(index)
+}
 
-        for document in net::crawl(&CLIENT, crawl_envelope.0.clone(), visited.clone()).await {
-            let crawl_stack_ptr = crawl_stack.clone();
-            let url = crawl_envelope.0.clone();
-            let visited_ptr = crawl_envelope.1.clone();
-            let index_ptr = index.clone();
+/// Normalizes a host name for the `hosts` index, so that `www.` and apex
+/// domains are treated as the same site.
+pub fn host_key(host: &str) -> String {
+    let host = host.to_lowercase();
+    match host.strip_prefix("www.") {
+        Some(apex) => apex.to_string(),
+        None => host,
+    }
+}
 
-            println!("Crawl stack loop: {:#?}", url);
-            handles.push(task::spawn(async move {
-                let mut visited_url = Url::parse(&document.url).unwrap();
-                visited_url.set_query(None);
-                visited_url.set_fragment(None);
+fn shrink_index(index: &mut Index) {
+    println!("Shrinking all indexed document sets.");
+    for postings in index.unigrams.values_mut() {
+        postings.shrink_to_fit();
+    }
+}
 
-                if visited_ptr.lock().unwrap().insert(visited_url.clone()) {
-                    println!("Saving texts.");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                    // TODO: Save text here?
+    fn tokenize(text: &str) -> Vec<String> {
+        StandardAnalyzer::plain().analyze(text)
+    }
 
-                    // Guard against traversing to other origins.
-                    if visited_url.origin() == url.origin() {
-                        println!("Attempting to push to stack.");
-                        crawl_stack_ptr
-                            .lock()
-                            .unwrap()
-                            .push((visited_url, visited_ptr.clone()));
-                        println!("Pushed to stack.");
-                        println!("Attempting to write to index.");
+    fn index(documents: &[(&str, &[&str])]) -> Index {
+        let mut index = Index::default();
+        for (url, texts) in documents {
+            index.index_texts(url.to_string(), texts.iter().map(|t| tokenize(t)).collect());
+        }
+        index
+    }
 
-                        // TODO: Clean up this block.
-                        // No need to index here; we will let quickwit handle this.
-                        // index_ptr.lock().unwrap().index_texts(id, texts);
+    fn phrase(s: &str) -> Option<HashSet<String>> {
+        index(&[
+            ("exact", &["the tail at scale"]),
+            ("scattered", &["the tail wags, at scale"]),
+            ("split", &["the tail", "at scale"]),
+        ])
+        .exact_ngram_match(tokenize(s))
+    }
 
-                        println!("Wrote to index.");
-                    }
-                }
-            }));
-        }
+    #[test]
+    fn phrases_only_match_adjacent_words() {
+        assert_eq!(
+            phrase("tail at scale"),
+            Some(HashSet::from(["exact".to_string()]))
+        );
+        assert_eq!(phrase("scale at"), None);
     }
 
-    future::join_all(handles).await;
-    index
-}
+    #[test]
+    fn phrases_never_span_text_nodes() {
+        assert_eq!(
+            phrase("the tail"),
+            Some(HashSet::from(
+                ["exact", "scattered", "split"].map(String::from)
+            ))
+        );
+        assert_eq!(
+            phrase("tail at"),
+            Some(HashSet::from(["exact".to_string()]))
+        );
+    }
 
-fn shrink_index(index: Arc<Mutex<Index>>) {
-    println!("Shrinking all indexed document sets.");
-    let mut index_guard = index.lock().unwrap();
-    for indexed_documents in index_guard.ngrams.values_mut() {
-        indexed_documents.shrink_to_fit();
+    #[test]
+    fn positions_follow_the_texts_in_order() {
+        let index = index(&[("post", &["tail latency", "at scale", "tail at scale"])]);
+        let positions = |word: &str| -> Vec<u32> {
+            let mut postings = index.unigram_postings(word).unwrap().cursor();
+            postings.seek(0).unwrap().collect()
+        };
+
+        assert_eq!(positions("tail"), [0, 6]);
+        assert_eq!(positions("latency"), [1]);
+        assert_eq!(positions("at"), [3, 7]);
+        assert_eq!(positions("scale"), [4, 8]);
+        assert_eq!(index.ngram_documents(&tokenize("latency at")).len(), 0);
+    }
+
+    #[test]
+    fn repeated_texts_all_count() {
+        let index = index(&[("faq", &["Latency matters.", "Latency matters."])]);
+        let mut postings = index.unigram_postings("latency").unwrap().cursor();
+        assert_eq!(postings.seek(0).unwrap().frequency(), 2);
+        assert_eq!(index.document_lengths, [4]);
+    }
+
+    #[test]
+    fn collapses_near_duplicate_documents() {
+        let post = "Most latency in a datacenter comes from the tail. A request that fans out to \
+            a hundred servers is as slow as the slowest of them, so the p99 of each server \
+            becomes the median of the whole request. Hedged requests and tied requests are two \
+            ways to cut it.";
+        let mut index = Index::default();
+        for (url, texts) in [
+            ("https://danluu.com/tail/index.html", vec!["Archive", post]),
+            ("https://danluu.com/tail/", vec![post]),
+            (
+                "https://danluu.com/cache/",
+                vec!["Caches are fast when the working set is small."],
+            ),
+        ] {
+            index.index_document(SearchableDocument {
+                url: url.to_string(),
+                searchable_texts: texts.into_iter().map(String::from).collect(),
+                ..Default::default()
+            });
+        }
+
+        index.collapse_duplicates();
+        let duplicates: Vec<&str> = index
+            .duplicates
+            .iter()
+            .map(|d| index.document_url(d))
+            .collect();
+        assert_eq!(duplicates, vec!["https://danluu.com/tail/index.html"]);
     }
 
-    for indexed_documents in index_guard.unigrams.values_mut() {
-        indexed_documents.shrink_to_fit();
+    #[test]
+    fn indexes_authors_tags_and_publication_dates() {
+        let mut index = Index::default();
+        for (url, author, tags, published_at) in [
+            (
+                "https://aws.example/timeouts",
+                Some("Marc Brooker"),
+                vec!["Resilience"],
+                Some(1_500),
+            ),
+            (
+                "https://aws.example/caching",
+                Some("Matt Brinkley"),
+                vec!["Architecture"],
+                Some(2_500),
+            ),
+            ("https://aws.example/undated", None, vec![], None),
+        ] {
+            index.index_document(SearchableDocument {
+                url: url.to_string(),
+                author: author.map(String::from),
+                tags: tags.into_iter().map(String::from).collect(),
+                published_at_linux_epoch_secs: published_at,
+                ..Default::default()
+            });
+        }
+        let urls = |documents: RoaringBitmap| -> Vec<&str> {
+            documents.iter().map(|d| index.document_url(d)).collect()
+        };
+
+        assert_eq!(
+            urls(index.author_documents(&tokenize("brooker"))),
+            ["https://aws.example/timeouts"]
+        );
+        assert_eq!(
+            urls(index.tag_documents("architecture")),
+            ["https://aws.example/caching"]
+        );
+        assert_eq!(
+            urls(index.published_documents(2_000..)),
+            ["https://aws.example/caching"]
+        );
+        assert_eq!(
+            index.published_at("https://aws.example/timeouts"),
+            Some(1_500)
+        );
+        assert_eq!(index.published_at("https://aws.example/undated"), None);
+        // Authors and tags are searchable words too.
+        assert!(index.unigram_postings("resili").is_some());
     }
 }
@@ -1,13 +1,89 @@
 #[macro_use]
 extern crate lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use url::Url;
 
+pub mod analysis;
+pub mod builders_library;
+pub mod config;
+pub mod dedup;
+pub mod discovery;
 pub mod document;
+pub mod frontier;
+pub mod git;
+pub mod index;
 pub mod net;
+pub mod pdf;
+pub mod postings;
+pub mod query;
+pub mod rank;
+pub mod robots;
+pub mod scheduler;
+pub mod scope;
+pub mod server;
+pub mod snapshot;
+pub mod source;
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "RawConfig")]
 pub struct Config {
     pub websites: Vec<Website>,
+
+    /// Local git clones whose history and docs are indexed alongside the websites.
+    pub repositories: Vec<Repository>,
+
+    /// The other sources: feeds, JSON dumps and local directories.
+    pub sources: Vec<source::SourceConfig>,
+
+    /// How documents and queries are tokenized. Changing this requires
+    /// rebuilding the index.
+    pub analyzer: analysis::StandardAnalyzer,
+}
+
+impl Config {
+    /// Every source, websites first.
+    pub fn sources(&self) -> Vec<&dyn source::Source> {
+        let mut sources: Vec<&dyn source::Source> = vec![];
+        sources.extend(self.websites.iter().map(|w| w as &dyn source::Source));
+        sources.extend(self.repositories.iter().map(|r| r as &dyn source::Source));
+        sources.extend(self.sources.iter().map(|s| s as &dyn source::Source));
+        sources
+    }
+}
+
+/// `Config` as written, where websites and repositories may be listed either on
+/// their own or among the typed `[[sources]]`.
+#[derive(Deserialize)]
+struct RawConfig {
+    #[serde(default)]
+    websites: Vec<Website>,
+    #[serde(default)]
+    repositories: Vec<Repository>,
+    #[serde(default)]
+    sources: Vec<source::SourceConfig>,
+    #[serde(default)]
+    analyzer: analysis::StandardAnalyzer,
+}
+
+impl From<RawConfig> for Config {
+    fn from(raw: RawConfig) -> Self {
+        let mut config = Config {
+            websites: raw.websites,
+            repositories: raw.repositories,
+            sources: vec![],
+            analyzer: raw.analyzer,
+        };
+        for source in raw.sources {
+            match source {
+                source::SourceConfig::Website(website) => config.websites.push(website),
+                source::SourceConfig::Git(repository) => config.repositories.push(repository),
+                other => config.sources.push(other),
+            }
+        }
+        config
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,8 +91,191 @@ pub struct Website {
     pub url: String,
     #[serde(default = "default_recursively_crawl")]
     pub recursively_crawl: bool,
+
+    /// Whether subdomains of `url`'s host belong to the website too.
+    #[serde(default)]
+    pub include_subdomains: bool,
+
+    /// The minimum time between two requests to this website's host. Defaults
+    /// to `scheduler::Politeness::default()`.
+    pub crawl_delay_ms: Option<u64>,
+
+    /// How many requests to this website's host may be in flight at once.
+    pub max_connections: Option<usize>,
+
+    /// How long a crawled page is trusted before the crawler asks the server
+    /// whether it changed. Defaults to `DEFAULT_MAX_AGE_SECS`.
+    pub max_age_secs: Option<u64>,
+
+    /// How many links away from `url` the crawler may go. Pages listed in
+    /// sitemaps and feeds count as one link away. Unlimited by default.
+    pub max_depth: Option<u32>,
+
+    /// The most pages the crawler keeps for this website. Unlimited by default.
+    pub max_pages: Option<usize>,
+
+    /// Only pages whose path starts with one of these are crawled. Defaults to
+    /// the path of `url`, so `http://dtrace.org/blogs/ahl` stays in that blog.
+    #[serde(default)]
+    pub include_path_prefixes: Vec<String>,
+
+    /// Pages whose URL matches any of these regexes are never crawled.
+    #[serde(default, with = "regexes")]
+    pub exclude_patterns: Vec<Regex>,
+
+    /// `url` parsed, and the policy for which hosts are on its site: what
+    /// `in_scope` checks every link against, built the first time it's needed.
+    #[serde(skip)]
+    pub(crate) scope: std::sync::OnceLock<Option<Box<(Url, scope::ScopePolicy)>>>,
 }
 
+impl Website {
+    /// A website crawled with the default settings, as if configured with just a `url`.
+    pub fn new(url: &str) -> Self {
+        Website {
+            url: url.to_string(),
+            recursively_crawl: default_recursively_crawl(),
+            include_subdomains: false,
+            crawl_delay_ms: None,
+            max_connections: None,
+            max_age_secs: None,
+            max_depth: None,
+            max_pages: None,
+            include_path_prefixes: vec![],
+            exclude_patterns: vec![],
+            scope: Default::default(),
+        }
+    }
+
+    /// Whether `url` belongs to this website: it's on the same site (per
+    /// `scope::ScopePolicy`), under one of the included path prefixes, and not
+    /// excluded.
+    pub fn in_scope(&self, url: &Url) -> bool {
+        let scope = self.scope.get_or_init(|| {
+            let root = Url::parse(&self.url).ok()?;
+            Some(Box::new((root, scope::ScopePolicy::new(std::slice::from_ref(self)))))
+        });
+        let (root, policy) = match scope {
+            Some(scope) => &**scope,
+            None => return false,
+        };
+        let under = |prefix: &str| is_under(url.path(), prefix);
+
+        policy.contains(url)
+            && if self.include_path_prefixes.is_empty() {
+                under(root.path())
+            } else {
+                self.include_path_prefixes.iter().any(|p| under(p))
+            }
+            && !self.exclude_patterns.iter().any(|p| p.is_match(url.as_str()))
+    }
+
+    /// Whether the crawler may follow links out of a page `depth` links away from `url`.
+    pub fn follows_links_from(&self, depth: u32) -> bool {
+        self.max_depth.map_or(true, |max_depth| depth < max_depth)
+    }
+
+    pub fn max_age(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_age_secs.unwrap_or(DEFAULT_MAX_AGE_SECS))
+    }
+}
+
+/// Whether `path` is `prefix` or somewhere beneath it, so `/blogs/ahl/post` is
+/// under `/blogs/ahl` but `/blogs/ahlberg` isn't.
+pub fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix.trim_end_matches('/'))
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Repository {
+    /// Where the clone is on disk.
+    pub path: PathBuf,
+
+    /// Where the repository can be browsed, e.g. `https://github.com/jmqd/folklore.dev`.
+    /// Its documents are linked beneath it. Defaults to the `file://` URL of `path`.
+    pub url: Option<String>,
+}
+
+impl Repository {
+    /// The URL that the repository's documents are linked beneath.
+    pub fn base_url(&self) -> std::io::Result<Url> {
+        base_url(self.url.as_deref(), &self.path)
+    }
+}
+
+/// `url`, or failing that the `file://` URL of the directory at `path`.
+fn base_url(url: Option<&str>, path: &std::path::Path) -> std::io::Result<Url> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    match url {
+        Some(url) => Url::parse(url).map_err(|e| invalid(format!("{}: {}", url, e))),
+        None => {
+            let path = path.canonicalize()?;
+            Url::from_directory_path(&path).map_err(|_| invalid(format!("{} isn't absolute", path.display())))
+        }
+    }
+}
+
+/// A week: blogs don't change that often, but new posts should show up eventually.
+pub const DEFAULT_MAX_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+
 fn default_recursively_crawl() -> bool {
     true
 }
+
+/// (De)serializes regexes as their source strings.
+mod regexes {
+    use regex::Regex;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(regexes: &[Regex], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(regexes.iter().map(|r| r.as_str()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pattern| Regex::new(pattern).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_websites_by_path_and_pattern() {
+        let config: Config = toml::from_str(
+            r#"
+            [[websites]]
+            url = "http://dtrace.org/blogs/ahl"
+
+            [[websites]]
+            url = "https://danluu.com"
+            include_path_prefixes = ["/posts/", "/about"]
+            exclude_patterns = ['\?page=\d+$', "/drafts/"]
+            "#,
+        )
+        .unwrap();
+        let (ahl, danluu) = (&config.websites[0], &config.websites[1]);
+
+        for (website, url, in_scope) in [
+            (ahl, "http://dtrace.org/blogs/ahl", true),
+            (ahl, "http://dtrace.org/blogs/ahl/2014/01/01/post", true),
+            (ahl, "http://dtrace.org/blogs/bmc/2014/01/01/post", false),
+            (ahl, "http://dtrace.org/blogs/ahlberg/2014/01/01/post", false),
+            (ahl, "http://example.com/blogs/ahl/post", false),
+            (danluu, "https://danluu.com/posts/cache", true),
+            (danluu, "https://danluu.com/about/", true),
+            (danluu, "https://danluu.com/", false),
+            (danluu, "https://danluu.com/posts/list?page=2", false),
+            (danluu, "https://danluu.com/posts/drafts/x", false),
+        ] {
+            assert_eq!(website.in_scope(&Url::parse(url).unwrap()), in_scope, "{}", url);
+        }
+
+        assert!(toml::from_str::<Config>("[[websites]]\nurl = \"a\"\nexclude_patterns = [\"(\"]").is_err());
+    }
+}
@@ -1,38 +1,148 @@
 use crate::document;
+use crate::frontier::{Entry, Frontier};
+use crate::pdf;
+use crate::robots::RobotsCache;
+use crate::scheduler::{self, Scheduler};
+use crate::scope::ScopePolicy;
+use crate::Website;
 use itertools::Itertools;
 use urlnorm;
 use reqwest;
 use select::document::Document;
-use select::predicate::Name;
+use select::predicate::{Attr, Name, Predicate};
 use serde::{Deserialize, Serialize};
 use serde_json;
+use sha2::{Digest, Sha256};
 
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashSet;
+use std::sync::Mutex;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
 use tokio::task;
 use tokio::time;
-use url::{ParseError, Url};
+use unicode_segmentation::UnicodeSegmentation;
+use url::Url;
 
 gflags::define! {
     /// The output directory for saving the crawled text files.
-    --output_dir <OUTPUT_DIR> = "/home/jmq/src/folklore.dev/output/"
+    pub --output_dir <OUTPUT_DIR> = "/home/jmq/src/folklore.dev/output/"
+}
+
+gflags::define! {
+    /// The User-Agent we crawl with. Its product token (before the `/`) picks
+    /// which robots.txt group applies to us.
+    pub --user_agent <USER_AGENT> = "folklore/0.1 (+https://folklore.dev)"
 }
 
 lazy_static!{
     static ref URL_NORMALIZER: urlnorm::UrlNormalizer = urlnorm::UrlNormalizer::default();
 }
 
-#[derive(Serialize, Deserialize)]
+/// Where failed fetches are recorded, under the output directory.
+pub const FAILURES_DIR: &str = "failures";
+
+/// Where the URLs that led to a saved document (redirects and non-canonical
+/// URLs) are recorded, under the output directory, each pointing at the URL
+/// the document is saved under.
+pub const ALIASES_DIR: &str = "aliases";
+
+/// How many redirects `fetch` follows before giving up on a URL. (Also the
+/// limit RFC 9309 sets for robots.txt.)
+pub const MAX_REDIRECTS: usize = 5;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct SearchableDocument {
     pub url: String,
+    #[serde(default)]
     pub title: String,
+    #[serde(default)]
     pub fetched_at_linux_epoch_secs: u64,
+    #[serde(default)]
     pub searchable_texts: Vec<String>,
+    #[serde(default)]
     pub links_same_domain: Vec<String>,
+
+    /// The URLs that redirected to `url`, in the order we followed them.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// The validators the server sent, so that re-crawls can ask "has this
+    /// changed?" instead of downloading the page again.
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+
+    /// The hex SHA-256 of the response body.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+
+    /// When the page was published, as far as the page itself, or failing
+    /// that a sitemap or feed, told us.
+    #[serde(default)]
+    pub published_at_linux_epoch_secs: Option<u64>,
+
+    /// The headings in the page's main content, in document order. They're
+    /// indexed along with, but kept out of, `searchable_texts`.
+    #[serde(default)]
+    pub headings: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// The `<html lang>`, e.g. "en-US".
+    #[serde(default)]
+    pub language: Option<String>,
+    /// The number of words in `searchable_texts`.
+    #[serde(default)]
+    pub word_count: usize,
+    /// What the page is about, e.g. "Architecture", as its listing tagged it.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// How many pages the document has, if it's a PDF. Its `searchable_texts`
+    /// are then one per page.
+    #[serde(default)]
+    pub page_count: Option<usize>,
+}
+
+impl SearchableDocument {
+    /// Whether this copy is recent enough that we don't need to ask the server about it.
+    pub fn is_fresh(&self, max_age: time::Duration) -> bool {
+        now_linux_epoch_secs().saturating_sub(self.fetched_at_linux_epoch_secs) < max_age.as_secs()
+    }
+
+    /// Whether requesting `url` is how this copy may have been got: it's the
+    /// document's URL, or one that redirected or pointed to it.
+    fn was_fetched_at(&self, url: &reqwest::Url) -> bool {
+        self.url == url.as_str() || self.aliases.iter().any(|alias| alias == url.as_str())
+    }
+}
+
+/// Why `fetch` came back without a document.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum FetchFailure {
+    /// The URL, or a redirect we were sent to, is outside the allowed domains.
+    NotAllowed(String),
+    /// The redirects went on for longer than `MAX_REDIRECTS`, or had no usable Location.
+    BadRedirect(String),
+    /// The server answered with this (non-2xx, non-3xx) status.
+    Status(u16),
+    /// The response was neither HTML nor a PDF; this is its Content-Type.
+    Unsupported(String),
+    /// We got the body, but couldn't make sense of it.
+    Unreadable(String),
+    /// We couldn't get a response, or couldn't read its body.
+    Network(String),
+}
+
+/// A fetch that didn't produce a document, as recorded in `FAILURES_DIR`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FailedFetch {
+    pub url: String,
+    pub failure: FetchFailure,
+    pub fetched_at_linux_epoch_secs: u64,
 }
 
 pub fn url_to_string(url: &reqwest::Url) -> String {
@@ -41,100 +151,262 @@ pub fn url_to_string(url: &reqwest::Url) -> String {
 
 pub async fn crawl(
     client: &'static reqwest::Client,
-    url: reqwest::Url,
-    allowed_domains: &'static HashSet<String>
+    entry: &Entry,
+    website: &'static Website,
+    scope: &'static ScopePolicy,
+    robots: &'static RobotsCache,
+    scheduler: &'static Scheduler,
+    frontier: &Mutex<Frontier>,
 ) -> Vec<SearchableDocument> {
+    let url = match Url::parse(&entry.url) {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("Can't crawl {}: {}", entry.url, e);
+            return vec![]
+        }
+    };
+    let follow_links = website.follows_links_from(entry.depth);
+    if !website.in_scope(&url) || !frontier.lock().unwrap().has_page_budget(website, url.as_str(), 0) {
+        return vec![]
+    }
+    if !robots.is_allowed(client, scheduler, &url).await {
+        eprintln!("robots.txt disallows {}, skipping.", url);
+        return vec![]
+    }
+    scheduler.set_crawl_delay(&url, robots.get(client, scheduler, &url).await.crawl_delay);
+
     let mut documents = Vec::new();
     // TODO: Rename root to something more useful.
-    let root_document = fetch(client, &url, 0, allowed_domains).await;
+    let root_document = match read_cached(Path::new(OUTPUT_DIR.flag), &url) {
+        Some(cached) if cached.is_fresh(website.max_age()) => Some(cached),
+        cached => fetch_and_save(client, scheduler, &url, scope, cached).await,
+    };
+
+    // The sitemap, feed or directory that listed this URL may know when it was
+    // published, who wrote it, and what it's about.
+    let root_document = root_document.map(|mut document| {
+        let mut changed = false;
+        if document.published_at_linux_epoch_secs.is_none() && entry.published_at_linux_epoch_secs.is_some() {
+            document.published_at_linux_epoch_secs = entry.published_at_linux_epoch_secs;
+            changed = true;
+        }
+        if document.author.is_none() && entry.author.is_some() {
+            document.author = entry.author.clone();
+            changed = true;
+        }
+        for tag in &entry.tags {
+            if !document.tags.contains(tag) {
+                document.tags.push(tag.clone());
+                changed = true;
+            }
+        }
+        if changed {
+            if let Err(e) = save_document(&document) {
+                eprintln!("Failed to save {}: {}", document.url, e);
+            }
+        }
+        document
+    });
 
     if root_document.is_none() {
         eprintln!("Failed to get root_document.");
         return vec![]
     }
+    // Only pages we got count against the budget, not ones robots.txt kept us
+    // from or that failed to fetch.
+    if !take_page(frontier, website, &url) {
+        return vec![]
+    }
 
     let urls: Vec<Url> = root_document
         .as_ref()
         .expect("Failed to unwrap root_document")
         .links_same_domain
         .iter()
+        .filter(|_| follow_links)
         .map(|s| Url::parse(s).expect("Failed to parse URL"))
+        .filter(|url| website.in_scope(url))
         .collect();
 
-    // TODO: Remove this duplication for the root element.
-    let local_fs_path = Path::new(OUTPUT_DIR.flag).join(url_to_filename(&url));
-    if let Some(writeable_doc) = root_document.as_ref() {
-        eprintln!("Creating file at {:?}", local_fs_path.as_os_str());
-        let mut file = File::create(&local_fs_path).expect("creating file");
-        file.write_all(&serde_json::to_vec(writeable_doc).expect("serializing searchabledoc"))
-            .expect("writing searchable doc");
+    documents.push(root_document);
 
-        eprintln!(
-            "Wrote a SearchableDocument to {}",
-            &local_fs_path.to_string_lossy()
-        )
+    for (url, handle) in fetch_links(client, website, scope, robots, scheduler, frontier, urls).await {
+        match handle.await {
+            Ok(Some(_)) if !take_page(frontier, website, &url) => {
+                println!("{} has used up its page budget, dropping {}", website.url, url);
+            }
+            Ok(document) => documents.push(document),
+            Err(e) => eprintln!("A fetch for {} failed: {}", website.url, e),
+        }
     }
 
-    documents.push(root_document);
+    documents.into_iter().flatten().collect()
+}
 
-    let mut handles: Vec<task::JoinHandle<Option<SearchableDocument>>> = vec![];
-    for url in urls.into_iter().filter(|l| link_looks_interesting(l)) {
-        let local_fs_path = Path::new(OUTPUT_DIR.flag).join(url_to_filename(&url));
+/// Starts fetching the `urls` that robots.txt allows, as many as the website's
+/// page budget has room for. The budget is only charged once a fetch returns a
+/// document, so that's left to the caller.
+async fn fetch_links(
+    client: &'static reqwest::Client,
+    website: &'static Website,
+    scope: &'static ScopePolicy,
+    robots: &'static RobotsCache,
+    scheduler: &'static Scheduler,
+    frontier: &Mutex<Frontier>,
+    urls: Vec<Url>,
+) -> Vec<(Url, task::JoinHandle<Option<SearchableDocument>>)> {
+    let mut handles = vec![];
+    for url in urls.into_iter().filter(link_looks_interesting) {
+        if !frontier.lock().unwrap().has_page_budget(website, url.as_str(), handles.len()) {
+            println!("{} has used up its page budget, skipping {}", website.url, url);
+            break;
+        }
 
-        if let Ok(_metadata) = std::fs::metadata(&local_fs_path) {
-            match serde_json::from_str(&std::fs::read_to_string(&local_fs_path).unwrap()) {
-                Ok(f) => {
-                    print!("H");
-                    handles.push(task::spawn(async move { return f }));
-                    continue;
-                }
-                Err(err) => {
-                    println!("Failed to demarshal {}", local_fs_path.display());
-                    println!("{:?}", err);
-                }
+        // A fresh copy on disk is as good as the real thing. A stale one still
+        // lets us ask the server whether anything has changed.
+        let cached = match read_cached(Path::new(OUTPUT_DIR.flag), &url) {
+            Some(cached) if cached.is_fresh(website.max_age()) => {
+                print!("H");
+                handles.push((url, task::spawn(async move { Some(cached) })));
+                continue;
             }
+            cached => cached,
+        };
+
+        if !robots.is_allowed(client, scheduler, &url).await {
+            println!("robots.txt disallows {}, skipping.", url);
+            continue;
         }
 
-        // Let's be nice to our friends' servers. If we need to go over the network
-        // to get the document contents (i.e. cache miss), let's take a breather first.
-        time::sleep(time::Duration::from_millis(64)).await;
+        // Let's be nice to our friends' servers: `fetch` waits on the scheduler,
+        // which spaces out and caps the requests we make to each host.
+        let link = url.clone();
+        handles.push((url, task::spawn(async move {
+            fetch_and_save(client, scheduler, &link, scope, cached).await
+        })));
+    }
+    handles
+}
 
-        handles.push(task::spawn(async move {
-            let searchable_doc = fetch(client, &url, 0, allowed_domains).await;
+/// Counts `url` against the website's `max_pages` in the frontier's journal,
+/// returning false if the budget is already spent (or can't be recorded).
+fn take_page(frontier: &Mutex<Frontier>, website: &Website, url: &reqwest::Url) -> bool {
+    frontier.lock().unwrap().take_page(website, url.as_str()).unwrap_or_else(|e| {
+        eprintln!("Failed to count {} against {}'s page budget: {}", url, website.url, e);
+        false
+    })
+}
 
-            if let Some(writeable_doc) = searchable_doc.as_ref() {
-                eprintln!("Creating file at {:?}", local_fs_path.as_os_str());
-                let mut file = File::create(&local_fs_path).expect("creating file");
-                file.write_all(
-                    &serde_json::to_vec(writeable_doc).expect("serializing searchabledoc"),
-                )
-                .expect("writing searchable doc");
+/// Fetches `url` and saves the result under `OUTPUT_DIR`: the document if we
+/// got one (keyed by the URL it ended up at, after redirects), or else a
+/// `FailedFetch` in the `failures` subdirectory.
+async fn fetch_and_save(
+    client: &reqwest::Client,
+    scheduler: &Scheduler,
+    url: &reqwest::Url,
+    scope: &ScopePolicy,
+    cached: Option<SearchableDocument>,
+) -> Option<SearchableDocument> {
+    let output_dir = Path::new(OUTPUT_DIR.flag);
 
-                eprintln!(
-                    "Wrote a SearchableDocument to {}",
-                    &local_fs_path.to_string_lossy()
-                )
+    match fetch(client, scheduler, url, scope, cached.as_ref()).await {
+        Ok(document) => {
+            if let Err(e) = save_document(&document) {
+                eprintln!("Failed to save {}: {}", document.url, e);
+            }
+            Some(document)
+        }
+        Err(failure) => {
+            eprintln!("Failed to fetch {}: {:?}", url, failure);
+            let failures_dir = output_dir.join(FAILURES_DIR);
+            let failed_fetch = FailedFetch {
+                url: url.to_string(),
+                failure,
+                fetched_at_linux_epoch_secs: now_linux_epoch_secs(),
+            };
+            if let Err(e) = std::fs::create_dir_all(&failures_dir)
+                .and_then(|_| write_json(&failures_dir.join(url_to_filename(url)), &failed_fetch))
+            {
+                eprintln!("Failed to record the failed fetch of {}: {}", url, e);
             }
+            None
+        }
+    }
+}
+
+/// Writes `document` into --output_dir, where the indexer picks it up. Fails
+/// if the document's URL doesn't parse, or the file can't be written.
+pub fn save_document(document: &SearchableDocument) -> std::io::Result<()> {
+    write_document(Path::new(OUTPUT_DIR.flag), document)
+}
 
-            searchable_doc
-        }));
+/// Writes `document` into `output_dir`, keyed by its URL, and records its
+/// aliases in `ALIASES_DIR` so `read_cached` can find it by them.
+fn write_document(output_dir: &Path, document: &SearchableDocument) -> std::io::Result<()> {
+    let url = Url::parse(&document.url).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("`{}` isn't a URL: {}", document.url, e))
+    })?;
+    write_json(&output_dir.join(url_to_filename(&url)), document)?;
+
+    let aliases: Vec<Url> = document.aliases.iter().filter_map(|alias| Url::parse(alias).ok()).collect();
+    if !aliases.is_empty() {
+        let aliases_dir = output_dir.join(ALIASES_DIR);
+        std::fs::create_dir_all(&aliases_dir)?;
+        for alias in aliases {
+            write_json(&aliases_dir.join(url_to_filename(&alias)), &document.url)?;
+        }
     }
+    Ok(())
+}
 
-    for handle in handles {
-        documents.push(handle.await.expect("awaiting handle"));
+/// The copy of `url` we saved in `output_dir` on an earlier crawl, if there is
+/// one, whether it was saved under `url` or under the URL `url` led to.
+fn read_cached(output_dir: &Path, url: &reqwest::Url) -> Option<SearchableDocument> {
+    let mut local_fs_path = output_dir.join(url_to_filename(url));
+    if !local_fs_path.exists() {
+        let alias_path = output_dir.join(ALIASES_DIR).join(url_to_filename(url));
+        let target: String = serde_json::from_str(&std::fs::read_to_string(alias_path).ok()?).ok()?;
+        local_fs_path = output_dir.join(url_to_filename(&Url::parse(&target).ok()?));
     }
+    let contents = std::fs::read_to_string(&local_fs_path).ok()?;
 
-    documents.into_iter().flatten().collect()
+    match serde_json::from_str(&contents) {
+        Ok(document) => Some(document),
+        Err(err) => {
+            println!("Failed to demarshal {}", local_fs_path.display());
+            println!("{:?}", err);
+            None
+        }
+    }
+}
+
+fn write_json<T: Serialize>(local_fs_path: &Path, value: &T) -> std::io::Result<()> {
+    eprintln!("Creating file at {:?}", local_fs_path.as_os_str());
+    let mut file = File::create(local_fs_path)?;
+    file.write_all(&serde_json::to_vec(value)?)?;
+
+    eprintln!("Wrote {}", &local_fs_path.to_string_lossy());
+    Ok(())
+}
+
+fn now_linux_epoch_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
 }
 
 pub fn url_to_filename(url: &reqwest::Url) -> String {
     format!("{}.json", url_to_string(url))
 }
 
+/// Reads back the `SearchableDocument` that `crawl` saved for `url`, if any.
+pub fn load_document(output_dir: &Path, url: &reqwest::Url) -> Option<SearchableDocument> {
+    let contents = std::fs::read_to_string(output_dir.join(url_to_filename(url))).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
 fn link_looks_interesting(link: &reqwest::Url) -> bool {
     lazy_static! {
         static ref DISALLOWED_ENDINGS: Vec<&'static str> = vec![
-            ".pdf", ".png", ".jpg", ".jpeg", ".gif", ".xml", ".rss", ".css", ".js", ".mov", ".svg", ".ps", ".z",
+            ".png", ".jpg", ".jpeg", ".gif", ".xml", ".rss", ".css", ".js", ".mov", ".svg", ".ps", ".z",
             ".zip", ".gz", ".rar", ".json", ".webp", ".mp4", ".mp3", ".bz2", ".tar" , ".js", ".mod", ".webm", ".iso",
             ".dsk"
         ];
@@ -146,108 +418,589 @@ fn link_looks_interesting(link: &reqwest::Url) -> bool {
         .all(|ending| !s.ends_with(ending))
 }
 
-fn extract_links_same_domain(domain: &Url, document: &Document, allowed_domains: &HashSet<String>) -> Vec<Url> {
-    let mut urls: Vec<Url> = vec![];
-    document.find(Name("a")).for_each(|node| {
-        let link = match node.attr("href") {
-            None => None,
-            Some(link) => Some(Url::parse(link)),
-        };
-
-        let link = match link {
-            Some(Ok(mut link)) => {
-                if link.origin() == domain.origin() && link.path() != domain.path() && allowed_domains.contains(domain.domain().unwrap()) {
-                    link.set_query(None);
-                    link.set_fragment(None);
-                    Some(link)
-                } else {
-                    None
-                }
+fn extract_links_same_domain(page: &Url, document: &Document, scope: &ScopePolicy) -> Vec<Url> {
+    document
+        .find(Name("a"))
+        .filter_map(|node| node.attr("href"))
+        .filter_map(|href| match page.join(href) {
+            Ok(link) => Some(link),
+            Err(e) => {
+                println!("Error with link {}: {:#?}", href, e);
+                None
             }
-            Some(Err(e)) => match e {
-                ParseError::RelativeUrlWithoutBase => {
-                    match domain.join(node.attr("href").expect("unwrapping href attr")) {
-                        Ok(mut link) => {
-                            link.set_query(None);
-                            link.set_fragment(None);
-                            Some(link)
-                        }
-                        Err(e) => {
-                            println!("Error when trying to fix link: {:#?}", e);
-                            None
-                        }
-                    }
-                }
-                _ => {
-                    println!("Error with link: {:#?}", e);
-                    None
-                }
-            },
-            _ => None,
-        };
-
-        if link.is_some() {
-            urls.push(link.expect("unwrapping link"));
-        }
-    });
+        })
+        .filter_map(|link| scope.canonicalize(&link))
+        .map(|mut link| {
+            link.set_query(None);
+            link.set_fragment(None);
+            link
+        })
+        .filter(|link| link.path() != page.path() || link.host_str() != page.host_str())
+        .collect()
+}
 
-    urls
+/// The URL a page's `<link rel="canonical">` points to, if it's in scope.
+fn canonical_url(page: &Url, document: &Document, scope: &ScopePolicy) -> Option<Url> {
+    let href = document
+        .find(Name("link").and(Attr("rel", "canonical")))
+        .next()?
+        .attr("href")?;
+    let mut canonical = scope.canonicalize(&page.join(href.trim()).ok()?)?;
+    canonical.set_fragment(None);
+
+    // Some sites point every page's canonical at their front page. Taking that
+    // at its word would fold the whole site into a single document.
+    if canonical.path() == "/" && page.path() != "/" {
+        return None;
+    }
+    Some(canonical)
 }
 
 pub async fn parse_document(
     resp: reqwest::Response,
     url: &reqwest::Url,
-    allowed_domains: &HashSet<String>
+    scope: &ScopePolicy
 ) -> Option<SearchableDocument> {
-    if let Ok(body) = resp.text().await {
-        let doc = document::resp_to_document(body).await?;
-        let texts = document::extract_texts(&doc);
-
-        Some(SearchableDocument {
-            url: url.to_string(),
-            fetched_at_linux_epoch_secs: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
-            title: doc.find(Name("title")).next().map(|t| t.text()).unwrap_or("TODO".to_string()),
-            searchable_texts: texts.into_iter().unique().collect(),
-            links_same_domain: extract_links_same_domain(url, &doc, allowed_domains)
-                .into_iter()
-                .map(|u| u.to_string())
-                .collect(),
-        })
-    } else {
-        None
+    let (etag, last_modified) = validators(&resp);
+    let body = resp.text().await.ok()?;
+
+    Some(SearchableDocument {
+        etag,
+        last_modified,
+        ..html_document(&body, url, scope)
+    })
+}
+
+/// Builds the document for an HTML page found at `url`. Only links inside
+/// `scope` are kept, and the page's canonical URL is used if it's in `scope`.
+pub fn html_document(body: &str, url: &Url, scope: &ScopePolicy) -> SearchableDocument {
+    let doc = Document::from(body);
+    let content = document::extract_content(&doc);
+    let texts: Vec<String> = content.paragraphs.into_iter().unique().collect();
+    let metadata = document::extract_metadata(&doc);
+    let canonical = canonical_url(url, &doc, scope).filter(|canonical| canonical != url);
+
+    SearchableDocument {
+        url: canonical.as_ref().unwrap_or(url).to_string(),
+        fetched_at_linux_epoch_secs: now_linux_epoch_secs(),
+        title: metadata.title.unwrap_or_else(|| url.to_string()),
+        word_count: texts.iter().map(|text| text.unicode_words().count()).sum(),
+        searchable_texts: texts,
+        links_same_domain: extract_links_same_domain(url, &doc, scope)
+            .into_iter()
+            .map(|u| u.to_string())
+            .collect(),
+        aliases: canonical.map(|_| vec![url.to_string()]).unwrap_or_default(),
+        etag: None,
+        last_modified: None,
+        content_hash: Some(format!("{:x}", Sha256::digest(body.as_bytes()))),
+        published_at_linux_epoch_secs: metadata.published_at_linux_epoch_secs,
+        headings: content.headings,
+        description: metadata.description,
+        author: metadata.author,
+        language: metadata.language,
+        tags: vec![],
+        page_count: None,
     }
 }
 
+/// Turns a PDF response into a document with one searchable text per page.
+async fn parse_pdf(resp: reqwest::Response, url: &reqwest::Url) -> Result<SearchableDocument, FetchFailure> {
+    let (etag, last_modified) = validators(&resp);
+    let body = resp.bytes().await.map_err(|e| FetchFailure::Network(e.to_string()))?;
+
+    Ok(SearchableDocument {
+        etag,
+        last_modified,
+        ..pdf_document(&body, url)?
+    })
+}
+
+/// Builds the document for a PDF found at `url`.
+pub fn pdf_document(bytes: &[u8], url: &Url) -> Result<SearchableDocument, FetchFailure> {
+    let pdf = unreadable_on_panic(|| pdf::extract(bytes))?;
+
+    Ok(SearchableDocument {
+        url: url.to_string(),
+        title: pdf.title.unwrap_or_else(|| url.to_string()),
+        fetched_at_linux_epoch_secs: now_linux_epoch_secs(),
+        word_count: pdf.pages.iter().map(|page| page.unicode_words().count()).sum(),
+        page_count: Some(pdf.pages.len()),
+        searchable_texts: pdf.pages,
+        content_hash: Some(format!("{:x}", Sha256::digest(bytes))),
+        ..Default::default()
+    })
+}
+
+/// Runs `extract` over a file we can't trust, so a parser that panics on a
+/// malformed one (as lopdf can) just makes the file `Unreadable`.
+fn unreadable_on_panic<T, E: ToString>(
+    extract: impl FnOnce() -> Result<T, E> + std::panic::UnwindSafe,
+) -> Result<T, FetchFailure> {
+    match std::panic::catch_unwind(extract) {
+        Ok(result) => result.map_err(|e| FetchFailure::Unreadable(e.to_string())),
+        Err(panic) => {
+            let message = (panic.downcast_ref::<&str>().map(|m| m.to_string()))
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(FetchFailure::Unreadable(format!("the parser panicked: {}", message)))
+        }
+    }
+}
+
+/// Builds the document for a markdown or plain text file at `url`. Its title
+/// is its first heading, or `name` if it has none.
+pub fn text_document(text: &str, url: &Url, name: &str) -> SearchableDocument {
+    let blocks = document::markdown_blocks(text);
+    let (headings, texts): (Vec<_>, Vec<_>) = blocks.into_iter().partition(|(is_heading, _)| *is_heading);
+    let headings: Vec<String> = headings.into_iter().map(|(_, heading)| heading).collect();
+    let texts: Vec<String> = texts.into_iter().map(|(_, text)| text).unique().collect();
+
+    SearchableDocument {
+        url: url.to_string(),
+        title: headings.first().cloned().unwrap_or_else(|| name.to_string()),
+        fetched_at_linux_epoch_secs: now_linux_epoch_secs(),
+        word_count: texts.iter().map(|text| text.unicode_words().count()).sum(),
+        searchable_texts: texts,
+        headings,
+        content_hash: Some(format!("{:x}", Sha256::digest(text.as_bytes()))),
+        ..Default::default()
+    }
+}
+
+/// The response's ETag and Last-Modified headers.
+fn validators(resp: &reqwest::Response) -> (Option<String>, Option<String>) {
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    (header(reqwest::header::ETAG), header(reqwest::header::LAST_MODIFIED))
+}
+
+/// Fetches `url` and parses it into a document, following redirects that stay
+/// inside `scope`. Only 2xx HTML and PDF responses are parsed.
+///
+/// With a `cached` copy of the document, the request is conditional, and a
+/// `304 Not Modified` gives back the cached copy with a new fetch time.
 pub async fn fetch(
     client: &reqwest::Client,
+    scheduler: &Scheduler,
     url: &reqwest::Url,
-    mut attempt: u64,
-    allowed_domains: &HashSet<String>
-) -> Option<SearchableDocument> {
-    if !allowed_domains.contains(url.domain().unwrap()) {
-        println!("{}", url);
-        return None;
+    scope: &ScopePolicy,
+    cached: Option<&SearchableDocument>,
+) -> Result<SearchableDocument, FetchFailure> {
+    let Resolved { resp, permit, url, mut aliases } =
+        resolve(client, scheduler, url, scope, cached).await?;
+    let status = resp.status();
+
+    if status == reqwest::StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached.filter(|c| c.was_fetched_at(&url)) {
+            println!("{} is unchanged.", url);
+            // Fetched at an alias (e.g. the page names another URL as canonical).
+            if cached.url != url.as_str() {
+                aliases.push(url.to_string());
+            }
+            return Ok(SearchableDocument {
+                fetched_at_linux_epoch_secs: now_linux_epoch_secs(),
+                aliases,
+                ..cached.clone()
+            });
+        }
     }
 
-    match client.get(url.clone()).send().await {
-        Ok(resp) => parse_document(resp, url, allowed_domains).await,
-        Err(e) => {
-            while attempt < 4 {
+    if !status.is_success() {
+        return Err(FetchFailure::Status(status.as_u16()));
+    }
+
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .unwrap_or("text/html")
+        .to_ascii_lowercase();
+    let mut document = if content_type.starts_with("text/html") || content_type.starts_with("application/xhtml+xml") {
+        parse_document(resp, &url, scope)
+            .await
+            .ok_or_else(|| FetchFailure::Network(format!("couldn't read the body of {}", url)))?
+    } else if content_type.starts_with("application/pdf") {
+        parse_pdf(resp, &url).await?
+    } else {
+        return Err(FetchFailure::Unsupported(content_type));
+    };
+    drop(permit);
+
+    aliases.append(&mut document.aliases);
+    document.aliases = aliases;
+    Ok(document)
+}
+
+/// Fetches the body of `url` as text, whatever its Content-Type, following
+/// redirects like `fetch` does. Returns the URL the body came from.
+pub async fn fetch_text(
+    client: &reqwest::Client,
+    scheduler: &Scheduler,
+    url: &reqwest::Url,
+    scope: &ScopePolicy,
+) -> Result<(reqwest::Url, String), FetchFailure> {
+    let Resolved { resp, permit, url, .. } = resolve(client, scheduler, url, scope, None).await?;
+
+    if !resp.status().is_success() {
+        return Err(FetchFailure::Status(resp.status().as_u16()));
+    }
+
+    let body = resp.text().await.map_err(|e| FetchFailure::Network(e.to_string()))?;
+    drop(permit);
+    Ok((url, body))
+}
+
+/// The first response for a URL that isn't a redirect to follow, and how we got to it.
+struct Resolved {
+    resp: reqwest::Response,
+    permit: scheduler::Permit,
+    url: reqwest::Url,
+    aliases: Vec<String>,
+}
+
+/// Requests `url`, following redirects as long as they stay inside
+/// `scope`. Requests for the `cached` document's URL are conditional.
+async fn resolve(
+    client: &reqwest::Client,
+    scheduler: &Scheduler,
+    url: &reqwest::Url,
+    scope: &ScopePolicy,
+    cached: Option<&SearchableDocument>,
+) -> Result<Resolved, FetchFailure> {
+    let mut target = url.clone();
+    let mut aliases: Vec<String> = vec![];
+
+    loop {
+        if !scope.contains(&target) {
+            return Err(FetchFailure::NotAllowed(target.to_string()));
+        }
+
+        let cached = cached.filter(|c| c.was_fetched_at(&target));
+        let (resp, permit) = send(client, scheduler, &target, conditional_headers(cached)).await?;
+        let status = resp.status();
+
+        if !status.is_redirection() || status == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(Resolved { resp, permit, url: target, aliases });
+        }
+
+        let location = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| target.join(l).ok());
+
+        match location {
+            Some(location) if aliases.len() < MAX_REDIRECTS => {
+                println!("{} redirects to {}", target, location);
+                aliases.push(target.to_string());
+                target = location;
+            }
+            _ => return Err(FetchFailure::BadRedirect(target.to_string())),
+        }
+    }
+}
+
+/// `If-None-Match` and `If-Modified-Since` for the validators of a cached document.
+fn conditional_headers(cached: Option<&SearchableDocument>) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let cached = match cached {
+        Some(cached) => cached,
+        None => return headers,
+    };
+
+    for (name, value) in [
+        (reqwest::header::IF_NONE_MATCH, &cached.etag),
+        (reqwest::header::IF_MODIFIED_SINCE, &cached.last_modified),
+    ] {
+        if let Some(value) = value.as_deref().and_then(|v| v.parse().ok()) {
+            headers.insert(name, value);
+        }
+    }
+
+    headers
+}
+
+/// Sends a GET for `url` once the scheduler lets us, retrying network errors
+/// and throttled responses. The permit is held until the body has been read.
+async fn send(
+    client: &reqwest::Client,
+    scheduler: &Scheduler,
+    url: &reqwest::Url,
+    headers: reqwest::header::HeaderMap,
+) -> Result<(reqwest::Response, scheduler::Permit), FetchFailure> {
+    let mut last_error = String::new();
+
+    for attempt in 1..=4 {
+        let permit = scheduler.acquire(url).await;
+        match client.get(url.clone()).headers(headers.clone()).send().await {
+            Ok(resp) => {
+                scheduler.record(url, resp.status(), scheduler::retry_after(&resp));
+                if scheduler::is_throttled(resp.status()) && attempt < 4 {
+                    // The scheduler holds our next attempt back until the host is ready.
+                    continue;
+                }
+                return Ok((resp, permit));
+            }
+            Err(e) => {
+                drop(permit);
                 println!("Error when getting site (attempt {}): {}", attempt, e);
-                attempt += 1;
+                last_error = e.to_string();
                 time::sleep(time::Duration::from_millis(attempt * 512)).await;
-                match client.get(url.as_str()).send().await {
-                    Ok(resp) => {
-                        return parse_document(resp, url, allowed_domains).await;
-                    }
-                    Err(e) => {
-                        eprintln!("Error getting site: {:#?}", e);
-                    }
-                }
             }
+        }
+    }
 
-            // We tried 4 times, but couldn't get the document.
-            None
+    // We tried 4 times, but couldn't get the document.
+    Err(FetchFailure::Network(last_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use crate::Website;
+
+    const PAGE: &str =
+        "<html><head><title>Hello</title></head><body><h1>Folklore</h1><p>Some folklore.</p></body></html>";
+
+    /// Serves a handful of pages, each answering with a different kind of response.
+    async fn serve() -> (reqwest::Client, Scheduler, ScopePolicy, String) {
+        let app = Router::new()
+            .route("/robots.txt", get(|| async { "User-agent: *\nDisallow: /private\n" }))
+            .route("/page", get(|| async { ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], PAGE) }))
+            .route("/moved", get(|| async { (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, "/page")]) }))
+            .route("/loop", get(|| async { (StatusCode::FOUND, [(header::LOCATION, "/loop")]) }))
+            .route("/away", get(|| async {
+                (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, "https://elsewhere.example/")])
+            }))
+            .route("/page/index.html", get(|| async {
+                let page = PAGE.replace("<head>", r#"<head><link rel="canonical" href="/page#top">"#);
+                ([(header::CONTENT_TYPE, "text/html")], page)
+            }))
+            .route("/gone", get(|| async { (StatusCode::NOT_FOUND, PAGE) }))
+            .route("/broken", get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, PAGE) }))
+            .route("/data", get(|| async { ([(header::CONTENT_TYPE, "application/json")], "{}") }))
+            .route("/paper.pdf", get(|| async {
+                let pdf = crate::pdf::tests::make_pdf(Some("Harvest, Yield"), &[&["Harvest"], &["and yield"]]);
+                ([(header::CONTENT_TYPE, "application/pdf")], pdf)
+            }))
+            .route("/corrupt.pdf", get(|| async { ([(header::CONTENT_TYPE, "application/pdf")], "%PDF-1.5 ...") }))
+            .route("/validated", get(|headers: header::HeaderMap| async move {
+                validated(&headers, PAGE.to_string())
+            }))
+            .route("/validated/index.html", get(|headers: header::HeaderMap| async move {
+                validated(&headers, PAGE.replace("<head>", r#"<head><link rel="canonical" href="/validated">"#))
+            }));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://localhost:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let website = Website { crawl_delay_ms: Some(0), ..Website::new(&base) };
+        let scope = ScopePolicy::new(std::slice::from_ref(&website));
+        (client, Scheduler::new(4, &[website]), scope, base)
+    }
+
+    /// Answers with a 304 if the request has `page`'s ETag, or else with `page`.
+    fn validated(headers: &header::HeaderMap, page: String) -> axum::response::Response {
+        if headers.get(header::IF_NONE_MATCH).map_or(false, |v| v == "\"v1\"") {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, "\"v1\"")], "").into_response();
+        }
+        let response_headers = [
+            (header::CONTENT_TYPE, "text/html"),
+            (header::ETAG, "\"v1\""),
+            (header::LAST_MODIFIED, "Mon, 13 Nov 2023 00:00:00 GMT"),
+        ];
+        (response_headers, page).into_response()
+    }
+
+    #[tokio::test]
+    async fn classifies_responses() {
+        let (client, scheduler, scope, base) = serve().await;
+        let fetch = |path: &str| {
+            let url = Url::parse(&format!("{}{}", base, path)).unwrap();
+            let (client, scheduler, scope) = (&client, &scheduler, &scope);
+            async move { fetch(client, scheduler, &url, scope, None).await }
+        };
+
+        let page = fetch("/page").await.unwrap();
+        assert_eq!(page.title, "Hello");
+        assert_eq!(page.word_count, 2);
+        assert_eq!(page.headings, vec!["Folklore"]);
+        assert_eq!(page.searchable_texts, vec!["Some folklore."]);
+        assert!(page.aliases.is_empty());
+
+        let moved = fetch("/moved").await.unwrap();
+        assert_eq!(moved.url, format!("{}/page", base));
+        assert_eq!(moved.aliases, vec![format!("{}/moved", base)]);
+
+        let duplicate = fetch("/page/index.html").await.unwrap();
+        assert_eq!(duplicate.url, format!("{}/page", base));
+        assert_eq!(duplicate.aliases, vec![format!("{}/page/index.html", base)]);
+
+        let paper = fetch("/paper.pdf").await.unwrap();
+        assert_eq!(paper.title, "Harvest, Yield");
+        assert_eq!(paper.searchable_texts, vec!["Harvest", "and yield"]);
+        assert_eq!((paper.page_count, paper.word_count), (Some(2), 3));
+
+        for (path, failure) in [
+            ("/away", FetchFailure::NotAllowed("https://elsewhere.example/".to_string())),
+            ("/loop", FetchFailure::BadRedirect(format!("{}/loop", base))),
+            ("/gone", FetchFailure::Status(404)),
+            ("/broken", FetchFailure::Status(500)),
+            ("/data", FetchFailure::Unsupported("application/json".to_string())),
+        ] {
+            assert_eq!(fetch(path).await.err(), Some(failure), "{}", path);
         }
+        assert!(matches!(fetch("/corrupt.pdf").await, Err(FetchFailure::Unreadable(_))));
+    }
+
+    #[test]
+    fn parser_panics_make_files_unreadable() {
+        assert_eq!(
+            unreadable_on_panic(|| -> Result<(), String> { panic!("bad xref") }),
+            Err(FetchFailure::Unreadable("the parser panicked: bad xref".to_string()))
+        );
+        assert_eq!(
+            unreadable_on_panic(|| Err::<(), _>("no pages")),
+            Err(FetchFailure::Unreadable("no pages".to_string()))
+        );
+        assert_eq!(unreadable_on_panic(|| Ok::<_, String>(1)), Ok(1));
+    }
+
+    #[tokio::test]
+    async fn revalidates_cached_documents() {
+        let (client, scheduler, scope, base) = serve().await;
+        let url = Url::parse(&format!("{}/validated", base)).unwrap();
+
+        let first = fetch(&client, &scheduler, &url, &scope, None).await.unwrap();
+        assert_eq!(first.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(first.last_modified.as_deref(), Some("Mon, 13 Nov 2023 00:00:00 GMT"));
+        assert_eq!(first.content_hash.as_ref().map(|h| h.len()), Some(64));
+
+        // Pretend the copy on disk is stale, with its text changed, so we can
+        // tell a 304 (which keeps it) from a fresh download.
+        let stale = SearchableDocument {
+            fetched_at_linux_epoch_secs: 0,
+            searchable_texts: vec!["cached".to_string()],
+            ..first.clone()
+        };
+        assert!(!stale.is_fresh(time::Duration::from_secs(3600)));
+
+        let second = fetch(&client, &scheduler, &url, &scope, Some(&stale)).await.unwrap();
+        assert_eq!(second.searchable_texts, vec!["cached".to_string()]);
+        assert!(second.is_fresh(time::Duration::from_secs(3600)));
+
+        // A cached copy without validators gets an unconditional request.
+        let unvalidated = SearchableDocument { etag: None, ..stale };
+        let third = fetch(&client, &scheduler, &url, &scope, Some(&unvalidated)).await.unwrap();
+        assert_eq!(third.searchable_texts, first.searchable_texts);
+    }
+
+    #[tokio::test]
+    async fn revalidates_documents_saved_under_their_canonical_url() {
+        let (client, scheduler, scope, base) = serve().await;
+        let output_dir = tempfile::tempdir().unwrap();
+        let url = Url::parse(&format!("{}/validated/index.html", base)).unwrap();
+
+        let first = fetch(&client, &scheduler, &url, &scope, None).await.unwrap();
+        assert_eq!(first.url, format!("{}/validated", base));
+        write_document(output_dir.path(), &first).unwrap();
+
+        // The copy saved under the canonical URL is found by the URL we crawl.
+        let cached = read_cached(output_dir.path(), &url).unwrap();
+        assert_eq!(cached.url, first.url);
+        let stale = SearchableDocument {
+            fetched_at_linux_epoch_secs: 0,
+            searchable_texts: vec!["cached".to_string()],
+            ..cached
+        };
+
+        let second = fetch(&client, &scheduler, &url, &scope, Some(&stale)).await.unwrap();
+        assert_eq!(second.searchable_texts, vec!["cached".to_string()]);
+        assert_eq!(second.aliases, vec![url.to_string()]);
+        assert!(read_cached(output_dir.path(), &Url::parse(&format!("{}/elsewhere", base)).unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn disallowed_pages_dont_use_the_page_budget() {
+        let (client, scheduler, scope, base) = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let frontier = Mutex::new(Frontier::open(&dir.path().join("frontier.jsonl")).unwrap());
+        let website = Website { max_pages: Some(1), crawl_delay_ms: Some(0), ..Website::new(&base) };
+        // `crawl` hands these to the fetches it spawns, like main's statics.
+        fn leak<T>(value: T) -> &'static T {
+            Box::leak(Box::new(value))
+        }
+        let (client, scheduler, scope, website) = (leak(client), leak(scheduler), leak(scope), leak(website));
+        let robots = leak(RobotsCache::new("folklore/0.1"));
+        let private = |path: &str| Url::parse(&format!("{}/private{}", base, path)).unwrap();
+
+        let root = Entry {
+            url: private("/").to_string(),
+            website: base.clone(),
+            depth: 0,
+            priority: 0,
+            published_at_linux_epoch_secs: None,
+            author: None,
+            tags: vec![],
+        };
+        assert!(crawl(client, &root, website, scope, robots, scheduler, &frontier).await.is_empty());
+        let links = vec![private("/a"), private("/b")];
+        assert!(fetch_links(client, website, scope, robots, scheduler, &frontier, links).await.is_empty());
+
+        // The one page the website may have is still up for grabs.
+        assert!(frontier.lock().unwrap().take_page(website, &format!("{}/page", base)).unwrap());
+    }
+
+    #[test]
+    fn refuses_to_save_documents_without_a_url() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let document = SearchableDocument { url: "not a url".to_string(), ..Default::default() };
+
+        let err = write_document(output_dir.path(), &document).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(std::fs::read_dir(output_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn extracts_links_in_scope() {
+        let scope = ScopePolicy::new(&[Website::new("https://danluu.com"), Website::new("http://127.0.0.1")]);
+        let page = Url::parse("https://www.danluu.com/posts/").unwrap();
+        let html = r##"
+            <a href="cache">relative</a>
+            <a href="/about?ref=home#top">rooted</a>
+            <a href="http://danluu.com/http">upgraded</a>
+            <a href="https://jm.dev/">elsewhere</a>
+            <a href="http://127.0.0.1/ip">ip host</a>
+            <a href="mailto:dan@danluu.com">mail</a>
+            <a href="#footnote">same page</a>
+        "##;
+
+        let links: Vec<String> = extract_links_same_domain(&page, &Document::from(html), &scope)
+            .iter()
+            .map(|u| u.to_string())
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                "https://www.danluu.com/posts/cache",
+                "https://www.danluu.com/about",
+                "https://danluu.com/http",
+                "http://127.0.0.1/ip",
+            ]
+        );
     }
 }
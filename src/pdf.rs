@@ -0,0 +1,175 @@
+use lopdf::{Dictionary, Document, Object};
+
+/// The text of a PDF, one string per page.
+#[derive(Debug, PartialEq)]
+pub struct Pdf {
+    /// The title from the PDF's metadata, or failing that its first line of text.
+    pub title: Option<String>,
+    pub pages: Vec<String>,
+}
+
+/// Pulls the text out of a PDF. Pages whose content we can't decode come back
+/// empty, so `pages` always has one entry per page.
+pub fn extract(bytes: &[u8]) -> Result<Pdf, lopdf::Error> {
+    let mut document = Document::load_mem(bytes)?;
+    if document.is_encrypted() {
+        // Plenty of PDFs are "encrypted" with an empty password, only to stop
+        // them being edited; anything else we can't read.
+        document.decrypt("")?;
+    }
+
+    let raw_pages: Vec<String> = document
+        .get_pages()
+        .keys()
+        .map(|number| document.extract_text(&[*number]).unwrap_or_default())
+        .collect();
+
+    let title = info(&document)
+        .and_then(|info| info.get(b"Title").ok())
+        .and_then(|title| title.as_str().ok())
+        .map(decode_text_string)
+        .map(|title| clean(&title))
+        .filter(|title| !title.is_empty())
+        .or_else(|| {
+            raw_pages
+                .iter()
+                .flat_map(|page| page.lines())
+                .map(clean)
+                .find(|line| !line.is_empty())
+        });
+
+    Ok(Pdf {
+        title,
+        pages: raw_pages.iter().map(|page| clean(page)).collect(),
+    })
+}
+
+/// The document information dictionary, which holds the title and author.
+fn info(document: &Document) -> Option<&Dictionary> {
+    match document.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => document.get_dictionary(*id).ok(),
+        Object::Dictionary(info) => Some(info),
+        _ => None,
+    }
+}
+
+/// Metadata strings are UTF-16BE if they start with a byte order mark, and
+/// PDFDocEncoding (close enough to Latin-1 for titles) otherwise.
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes {
+        [0xfe, 0xff, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+fn clean(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Stream, StringFormat};
+
+    /// Builds a PDF with one page per entry of `pages`, each a list of lines.
+    pub(crate) fn make_pdf(title: Option<&str>, pages: &[&[&str]]) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let font_id = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+
+        let mut kids = vec![];
+        for lines in pages {
+            let mut operations = vec![];
+            for line in lines.iter() {
+                operations.extend([
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![72.into(), 720.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(*line)]),
+                    Operation::new("ET", vec![]),
+                ]);
+            }
+            let content = Content { operations }.encode().unwrap();
+            let content_id = document.add_object(Stream::new(dictionary! {}, content));
+            kids.push(
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Page",
+                        "Parent" => pages_id,
+                        "Contents" => content_id,
+                    })
+                    .into(),
+            );
+        }
+
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Count" => kids.len() as i64,
+                "Kids" => kids,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        if let Some(title) = title {
+            // Written as UTF-16, as most PDF producers do.
+            let mut bytes = vec![0xfe, 0xff];
+            bytes.extend(title.encode_utf16().flat_map(u16::to_be_bytes));
+            let info_id = document.add_object(dictionary! {
+                "Title" => Object::String(bytes, StringFormat::Hexadecimal),
+            });
+            document.trailer.set("Info", info_id);
+        }
+
+        let mut bytes = vec![];
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn extracts_title_and_pages() {
+        let pages: &[&[&str]] = &[
+            &[
+                "Harvest, Yield, and Scalable Tolerant Systems",
+                "Armando Fox",
+            ],
+            &["Trading  harvest for yield."],
+        ];
+
+        assert_eq!(
+            extract(&make_pdf(Some("Harvest, Yield — Fox & Brewer"), pages)).unwrap(),
+            Pdf {
+                title: Some("Harvest, Yield — Fox & Brewer".to_string()),
+                pages: vec![
+                    "Harvest, Yield, and Scalable Tolerant Systems Armando Fox".to_string(),
+                    "Trading harvest for yield.".to_string(),
+                ],
+            }
+        );
+
+        let untitled = extract(&make_pdf(None, pages)).unwrap();
+        assert_eq!(
+            untitled.title.as_deref(),
+            Some("Harvest, Yield, and Scalable Tolerant Systems")
+        );
+
+        assert!(extract(b"<html>not a pdf</html>").is_err());
+    }
+}
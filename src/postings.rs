@@ -0,0 +1,227 @@
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+
+/// The compressed postings of a single term: which documents it appears in,
+/// and at which positions.
+///
+/// The documents are kept in a roaring bitmap, so boolean queries reduce to
+/// bitmap operations. The positions are kept in one byte stream, in document
+/// order: each document's positions are delta-encoded as LEB128 varints, and
+/// followed by a zero byte. (Deltas are stored plus one, so a varint never
+/// contains a zero byte, and a zero byte always ends a document.)
+#[derive(Default, Serialize, Deserialize)]
+pub struct PostingList {
+    documents: RoaringBitmap,
+    positions: Vec<u8>,
+
+    /// The last (document, position) pushed, so appends can be delta-encoded
+    /// without decoding the stream.
+    last: Option<(u32, u32)>,
+}
+
+impl PostingList {
+    /// Records an occurrence of the term at `position` within `document`.
+    ///
+    /// Occurrences are expected in increasing (document, position) order, which
+    /// is how `Index::index_texts` produces them. Anything else is still
+    /// handled, but by re-encoding the whole list.
+    pub fn push(&mut self, document: u32, position: u32) {
+        match self.last {
+            Some((d, p)) if d == document && p < position => {
+                // Replace the document's terminator with one more position.
+                self.positions.pop();
+                write_varint(&mut self.positions, position - p + 1);
+            }
+            Some(last) if last >= (document, position) => {
+                self.insert_out_of_order(document, position);
+                return;
+            }
+            _ => {
+                self.documents.insert(document);
+                write_varint(&mut self.positions, position + 1);
+            }
+        }
+
+        self.positions.push(0);
+        self.last = Some((document, position));
+    }
+
+    fn insert_out_of_order(&mut self, document: u32, position: u32) {
+        let mut decoded: BTreeMap<u32, Vec<u32>> = self
+            .iter()
+            .map(|(document, positions)| (document, positions.collect()))
+            .collect();
+
+        let positions = decoded.entry(document).or_default();
+        if let Err(i) = positions.binary_search(&position) {
+            positions.insert(i, position);
+        }
+
+        *self = PostingList::default();
+        for (document, positions) in decoded {
+            for position in positions {
+                self.push(document, position);
+            }
+        }
+    }
+
+    /// The documents containing the term.
+    pub fn documents(&self) -> &RoaringBitmap {
+        &self.documents
+    }
+
+    pub fn iter(&self) -> PostingIter<'_> {
+        PostingIter {
+            documents: self.documents.iter(),
+            positions: &self.positions,
+        }
+    }
+
+    /// A cursor for looking up documents in increasing order, which is how a
+    /// merge over several posting lists consumes them.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor {
+            postings: self.iter().peekable(),
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.positions.shrink_to_fit();
+    }
+}
+
+pub struct PostingIter<'a> {
+    documents: roaring::bitmap::Iter<'a>,
+    positions: &'a [u8],
+}
+
+impl<'a> Iterator for PostingIter<'a> {
+    type Item = (u32, Positions<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let document = self.documents.next()?;
+        let end = self.positions.iter().position(|b| *b == 0)?;
+        let positions = Positions {
+            bytes: &self.positions[..end],
+            last: 0,
+        };
+        self.positions = &self.positions[end + 1..];
+
+        Some((document, positions))
+    }
+}
+
+pub struct Cursor<'a> {
+    postings: Peekable<PostingIter<'a>>,
+}
+
+impl<'a> Cursor<'a> {
+    /// Advances to `document`, returning its positions if the term occurs
+    /// there. Documents must be sought in increasing order.
+    pub fn seek(&mut self, document: u32) -> Option<Positions<'a>> {
+        while self.postings.peek()?.0 < document {
+            self.postings.next();
+        }
+
+        match self.postings.peek() {
+            Some((d, _)) if *d == document => self.postings.next().map(|(_, p)| p),
+            _ => None,
+        }
+    }
+}
+
+/// The decoded positions of a term within one document, in increasing order.
+#[derive(Clone)]
+pub struct Positions<'a> {
+    bytes: &'a [u8],
+    last: u32,
+}
+
+impl<'a> Positions<'a> {
+    /// How many times the term occurs in the document.
+    pub fn frequency(&self) -> u32 {
+        // Every varint ends with exactly one byte without the continuation bit.
+        self.bytes.iter().filter(|b| *b & 0x80 == 0).count() as u32
+    }
+}
+
+impl<'a> Iterator for Positions<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        let (delta, rest) = read_varint(self.bytes);
+        self.bytes = rest;
+        self.last += delta - 1;
+        Some(self.last)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8]) -> (u32, &[u8]) {
+    let mut value = 0u32;
+    for (i, b) in bytes.iter().enumerate() {
+        value |= ((b & 0x7f) as u32) << (7 * i);
+        if b & 0x80 == 0 {
+            return (value, &bytes[i + 1..]);
+        }
+    }
+    (value, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(postings: &PostingList) -> Vec<(u32, Vec<u32>)> {
+        postings.iter().map(|(d, p)| (d, p.collect())).collect()
+    }
+
+    #[test]
+    fn round_trips_positions() {
+        let mut postings = PostingList::default();
+        for (document, position) in [(0, 0), (0, 1), (0, 300), (7, 5), (7, 70000), (9, 0)] {
+            postings.push(document, position);
+        }
+
+        assert_eq!(
+            decode(&postings),
+            [(0, vec![0, 1, 300]), (7, vec![5, 70000]), (9, vec![0])]
+        );
+
+        let mut cursor = postings.cursor();
+        assert_eq!(cursor.seek(0).map(|p| p.frequency()), Some(3));
+        assert!(cursor.seek(3).is_none());
+        assert_eq!(
+            cursor.seek(7).map(|p| p.collect::<Vec<_>>()),
+            Some(vec![5, 70000])
+        );
+        assert!(cursor.seek(10).is_none());
+    }
+
+    #[test]
+    fn accepts_out_of_order_pushes() {
+        let mut postings = PostingList::default();
+        for (document, position) in [(5, 3), (2, 9), (5, 1), (5, 3)] {
+            postings.push(document, position);
+        }
+        postings.push(6, 0);
+
+        assert_eq!(
+            decode(&postings),
+            [(2, vec![9]), (5, vec![1, 3]), (6, vec![0])]
+        );
+    }
+}
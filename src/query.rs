@@ -1,130 +1,694 @@
+use crate::analysis::Analyzer;
 use crate::index::Index;
-use regex::Regex;
-use std::collections::HashSet;
+use crate::rank::Bm25;
+use itertools::Itertools;
+use roaring::RoaringBitmap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::iter::Iterator;
 
-#[derive(Debug)]
-pub struct Query {
-    pub exact_ngram: Option<Vec<String>>,
-    pub unigrams: Option<Vec<String>>,
+/// A parsed search query.
+///
+/// The query language is a small boolean language over words:
+///
+/// * `latency tail` matches documents containing both words. (`AND` is
+///   implied, but may also be written out.)
+/// * `latency OR throughput` matches documents containing either word.
+/// * `-javascript` or `NOT javascript` excludes documents with the word.
+/// * `"tail at scale"` matches the exact phrase. A query may have any number
+///   of phrases.
+/// * `(cache OR caching) invalidation` groups sub-queries.
+/// * `site:danluu.com` restricts results to a host (and its subdomains), and
+///   `title:latency` or `title:"tail latency"` to documents whose title
+///   contains the given words.
+/// * `author:brooker` and `tag:architecture` restrict results to documents by
+///   that author or with that tag, and `after:2019` or `before:2021-06-01` to
+///   those published from the start of that year, month or day, or before it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(String),
+    /// Each word of the phrase, with its offset in the phrase. (Stop words
+    /// leave gaps, see `Analyzer::tokens`.)
+    Phrase(Vec<(u32, String)>),
+    Site(String),
+    Title(Vec<String>),
+    Author(Vec<String>),
+    Tag(String),
+    /// Published at or after this time, in seconds since the Unix epoch.
+    After(u64),
+    /// Published before this time, in seconds since the Unix epoch.
+    Before(u64),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
 }
 
-pub fn query(query_str: String, index: &Index) -> Option<HashSet<String>> {
-    lazy_static! {
-        static ref QUERY_PARSER: Regex =
-            Regex::new("(?:\"(?P<EXACT>.*)\"\\s*)?(?P<UNIGRAMS>.+)?").unwrap();
-    }
-
-    let captures = QUERY_PARSER.captures(&query_str).unwrap();
-    let mut query = Query {
-        exact_ngram: match captures.name("EXACT") {
-            None => None,
-            Some(exact) => Some(
-                exact
-                    .as_str()
-                    .split_whitespace()
-                    .into_iter()
-                    .map(|s| s.to_lowercase().to_string())
-                    .collect(),
-            ),
-        },
-        unigrams: match captures.name("UNIGRAMS") {
-            None => None,
-            Some(unigrams) => Some(
-                unigrams
-                    .as_str()
-                    .split_whitespace()
-                    .into_iter()
-                    .map(|s| s.to_lowercase().to_string())
-                    .collect(),
-            ),
-        },
-    };
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    EmptyQuery,
+    UnterminatedPhrase,
+    EmptyPhrase,
+    UnmatchedOpenParen,
+    UnmatchedCloseParen,
+
+    /// An operator (`OR`, `AND`, `NOT` or `-`) with nothing to operate on.
+    MissingOperand,
+
+    /// A field prefix like `site:` without a value.
+    MissingFieldValue,
+
+    /// An `after:` or `before:` whose value isn't a date.
+    InvalidDate,
+}
+
+/// Why a query string couldn't be parsed, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+
+    /// The byte offset into the query string where the problem was found.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problem = match self.kind {
+            ParseErrorKind::EmptyQuery => "the query has no searchable words",
+            ParseErrorKind::UnterminatedPhrase => "this phrase is missing its closing quote",
+            ParseErrorKind::EmptyPhrase => "this phrase has no searchable words",
+            ParseErrorKind::UnmatchedOpenParen => "this parenthesis is never closed",
+            ParseErrorKind::UnmatchedCloseParen => "this parenthesis was never opened",
+            ParseErrorKind::MissingOperand => "this operator is missing a search term",
+            ParseErrorKind::MissingFieldValue => "this field is missing a value",
+            ParseErrorKind::InvalidDate => "this date isn't written YYYY, YYYY-MM or YYYY-MM-DD",
+        };
+        write!(f, "{} (at character {})", problem, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Finds every document matching `query_str`, ordered by BM25 relevance.
+pub fn query(query_str: String, index: &Index) -> Result<Vec<(String, f32)>, ParseError> {
+    let query = parse(&query_str, &index.analyzer)?;
+    println!("Parsed query: {:#?}", query);
+
+    Ok(query.ranked_documents(index))
+}
+
+/// Parses `query_str`, turning its words into terms with `analyzer`. This
+/// must be the analyzer the index was built with.
+pub fn parse(query_str: &str, analyzer: &dyn Analyzer) -> Result<Query, ParseError> {
+    let tokens = lex(query_str, analyzer)?;
+    let mut parser = Parser { tokens, next: 0 };
+
+    if parser.tokens.is_empty() {
+        return Err(ParseError {
+            kind: ParseErrorKind::EmptyQuery,
+            position: 0,
+        });
+    }
+
+    let query = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(query),
+        // parse_or only stops early at a closing parenthesis.
+        Some((position, _)) => Err(ParseError {
+            kind: ParseErrorKind::UnmatchedCloseParen,
+            position,
+        }),
+    }
+}
+
+impl Query {
+    /// Finds every document matching the query, ordered by BM25 relevance.
+    pub fn ranked_documents(&self, index: &Index) -> Vec<(String, f32)> {
+        let terms: Vec<String> = self.positive_terms().into_iter().unique().collect();
+        let candidates = self.matching_documents(index) - &index.duplicates;
+        Bm25::default().rank(index, &terms, &candidates)
+    }
+
+    /// Evaluates the query to the set of matching document codes.
+    pub fn matching_documents(&self, index: &Index) -> RoaringBitmap {
+        match self {
+            Query::Term(word) => index
+                .unigram_postings(word)
+                .map(|postings| postings.documents().clone())
+                .unwrap_or_default(),
+            Query::Phrase(words) => index.phrase_documents(words),
+            Query::Site(host) => index.site_documents(host),
+            Query::Title(words) => index.title_documents(words),
+            Query::Author(words) => index.author_documents(words),
+            Query::Tag(tag) => index.tag_documents(tag),
+            Query::After(secs) => index.published_documents(*secs..),
+            Query::Before(secs) => index.published_documents(..*secs),
+            Query::Not(query) => index.all_documents() - query.matching_documents(index),
+            Query::Or(queries) => queries
+                .iter()
+                .map(|q| q.matching_documents(index))
+                .fold(RoaringBitmap::new(), |all, documents| all | documents),
+            Query::And(queries) => {
+                // Rather than materializing the complement of every negated
+                // operand, we intersect the positive operands and then remove
+                // whatever the negated ones match.
+                let (negated, positive): (Vec<&Query>, Vec<&Query>) =
+                    queries.iter().partition(|q| matches!(q, Query::Not(_)));
+
+                let mut documents = match positive.split_first() {
+                    None => index.all_documents(),
+                    Some((first, rest)) => {
+                        let mut documents = first.matching_documents(index);
+                        for query in rest {
+                            if documents.is_empty() {
+                                break;
+                            }
+                            documents &= query.matching_documents(index);
+                        }
+                        documents
+                    }
+                };
+
+                for query in negated {
+                    if let Query::Not(query) = query {
+                        documents -= query.matching_documents(index);
+                    }
+                }
 
-    // If the user provided an exact match like `"football" manchester`, we want
-    // to treat the quoted part just as if it's another unigram.
-    if query.exact_ngram.is_some() && query.exact_ngram.as_ref().unwrap().len() == 1 {
-        match query.unigrams {
-            Some(ref mut unigrams) => {
-                unigrams.push(query.exact_ngram.unwrap()[0].clone());
-                query.exact_ngram = None;
+                documents
             }
-            None => {
-                query.unigrams = Some(vec![query.exact_ngram.unwrap()[0].clone()]);
-                query.exact_ngram = None;
+        }
+    }
+
+    /// The words the user is looking for, i.e. those not inside a negation.
+    /// These are what results get ranked by.
+    pub fn positive_terms(&self) -> Vec<String> {
+        match self {
+            Query::Term(word) => vec![word.clone()],
+            Query::Phrase(words) => words.iter().map(|(_, word)| word.clone()).collect(),
+            Query::Title(words) | Query::Author(words) => words.clone(),
+            Query::Site(_) | Query::Tag(_) | Query::After(_) | Query::Before(_) | Query::Not(_) => {
+                vec![]
+            }
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter().flat_map(|q| q.positive_terms()).collect()
             }
         }
     }
-    println!("Parsed query: {:#?}", query);
+}
 
-    let mut unigram_result_set = HashSet::new();
-    if query.unigrams.is_some() {
-        let unigrams = query.unigrams.clone().unwrap();
-        let mut iter = unigrams.into_iter();
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(Vec<(u32, String)>),
+    Site(String),
+    Title(Vec<String>),
+    Author(Vec<String>),
+    Tag(String),
+    After(u64),
+    Before(u64),
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
 
-        // We seed the result set with the first unigram result set.
-        match index.unigram_match(iter.next().unwrap()) {
-            None => return None,
-            Some(results) => results.into_iter().for_each(|p| {
-                unigram_result_set.insert(p);
-            }),
+/// Splits a query string into tokens, each paired with its byte offset.
+fn lex(query_str: &str, analyzer: &dyn Analyzer) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = query_str.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push((start, Token::OpenParen));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((start, Token::CloseParen));
+            }
+            '"' => {
+                chars.next();
+                let words = analyzer.tokens(lex_phrase(query_str, start, &mut chars)?);
+                if words.is_empty() {
+                    return Err(ParseError {
+                        kind: ParseErrorKind::EmptyPhrase,
+                        position: start,
+                    });
+                }
+                tokens.push((start, Token::Phrase(words)));
+            }
+            '-' => {
+                chars.next();
+                // A lone or trailing dash is just punctuation, not a negation.
+                match chars.peek() {
+                    Some(&(_, c)) if !c.is_whitespace() && c != ')' => {
+                        tokens.push((start, Token::Not))
+                    }
+                    _ => {}
+                }
+            }
+            _ => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+
+                let word = &query_str[start..end];
+                let token = match word {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => match word.split_once(':') {
+                        Some((field, value)) if is_field(field) => {
+                            let value = match chars.peek() {
+                                Some(&(quote, '"')) if value.is_empty() => {
+                                    chars.next();
+                                    lex_phrase(query_str, quote, &mut chars)?
+                                }
+                                _ => value,
+                            };
+
+                            let field = field.to_ascii_lowercase();
+                            let exact = Some(value.trim().to_lowercase()).filter(|v| !v.is_empty());
+                            let words =
+                                Some(analyzer.analyze(value)).filter(|words| !words.is_empty());
+                            let token = match field.as_str() {
+                                // Host names and tags are matched as they are, not analyzed.
+                                "site" => exact.map(Token::Site),
+                                "tag" => exact.map(Token::Tag),
+                                "after" | "before" => match exact {
+                                    None => None,
+                                    Some(date) => {
+                                        let secs = parse_date(&date).ok_or(ParseError {
+                                            kind: ParseErrorKind::InvalidDate,
+                                            position: start,
+                                        })?;
+                                        Some(if field == "after" {
+                                            Token::After(secs)
+                                        } else {
+                                            Token::Before(secs)
+                                        })
+                                    }
+                                },
+                                "author" => words.map(Token::Author),
+                                _ => words.map(Token::Title),
+                            };
+
+                            match token {
+                                Some(token) => token,
+                                None => {
+                                    return Err(ParseError {
+                                        kind: ParseErrorKind::MissingFieldValue,
+                                        position: start,
+                                    })
+                                }
+                            }
+                        }
+                        _ => {
+                            let mut words = analyzer.tokens(word);
+                            match words.len() {
+                                // A stop word, or just punctuation. Any negation
+                                // in front of it is moot, so it goes too.
+                                0 => {
+                                    if let Some((_, Token::Not)) = tokens.last() {
+                                        tokens.pop();
+                                    }
+                                    continue;
+                                }
+                                1 => Token::Word(words.pop().unwrap().1),
+                                // e.g. `tail-latency`, which the index holds as
+                                // two adjacent words.
+                                _ => Token::Phrase(words),
+                            }
+                        }
+                    },
+                };
+                tokens.push((start, token));
+            }
         }
+    }
 
-        // All other unigram result sets will iteratively perform set intersection
-        // with the result set, to generate the final set of result candidates
-        for unigram in iter {
-            match index.unigram_match(unigram) {
-                Some(results) => {
-                    unigram_result_set = unigram_result_set
-                        .intersection(&results)
-                        .map(|s| s.to_string())
-                        .collect();
+    Ok(tokens)
+}
+
+fn is_field(name: &str) -> bool {
+    ["site", "title", "author", "tag", "after", "before"]
+        .iter()
+        .any(|field| name.eq_ignore_ascii_case(field))
+}
+
+/// The start of the year, month or day `date` names (`2021`, `2021-06` or
+/// `2021-06-01`), in seconds since the Unix epoch.
+fn parse_date(date: &str) -> Option<u64> {
+    let date = match date.len() {
+        4 => format!("{}-01-01", date),
+        7 => format!("{}-01", date),
+        _ => date.to_string(),
+    };
+    let date = chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?;
+    u64::try_from(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp()).ok()
+}
+
+/// Reads the rest of a phrase whose opening quote was at `start`, returning
+/// the text between the quotes.
+fn lex_phrase<'q>(
+    query_str: &'q str,
+    start: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<&'q str, ParseError> {
+    for (i, c) in chars.by_ref() {
+        if c == '"' {
+            return Ok(&query_str[start + 1..i]);
+        }
+    }
+
+    Err(ParseError {
+        kind: ParseErrorKind::UnterminatedPhrase,
+        position: start,
+    })
+}
+
+/// A recursive descent parser over the grammar:
+///
+/// ```text
+/// or      := and ("OR" and)*
+/// and     := unary ("AND"? unary)*
+/// unary   := ("NOT" | "-") unary | primary
+/// primary := "(" or ")" | word | phrase | field
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens.get(self.next).map(|(p, t)| (*p, t))
+    }
+
+    fn end_position(&self) -> usize {
+        self.tokens.last().map(|(p, _)| *p).unwrap_or(0)
+    }
+
+    fn parse_or(&mut self) -> Result<Query, ParseError> {
+        let mut operands = vec![self.parse_and()?];
+        while let Some((_, Token::Or)) = self.peek() {
+            self.next += 1;
+            operands.push(self.parse_and()?);
+        }
+
+        Ok(match operands.len() {
+            1 => operands.pop().unwrap(),
+            _ => Query::Or(operands),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Query, ParseError> {
+        let mut operands = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                None | Some((_, Token::Or)) | Some((_, Token::CloseParen)) => break,
+                Some((_, Token::And)) => {
+                    self.next += 1;
+                    operands.push(self.parse_unary()?);
                 }
-                None => unigram_result_set.clear(),
+                Some(_) => operands.push(self.parse_unary()?),
             }
         }
+
+        Ok(match operands.len() {
+            1 => operands.pop().unwrap(),
+            _ => Query::And(operands),
+        })
     }
 
-    let exact_results = match query.exact_ngram.clone() {
-        None => None,
-        Some(ngram) => {
-            let mut iter = ngram.as_slice().windows(2);
-            let mut ngram_result_set: HashSet<String> =
-                match index.ngram_match(iter.next().unwrap().to_vec()) {
-                    Some(results) => results.into_iter().collect(),
-                    None => HashSet::new(),
-                };
-            for bigram in iter {
-                match index.ngram_match(bigram.to_vec()) {
-                    Some(result) => {
-                        ngram_result_set = ngram_result_set
-                            .intersection(&result)
-                            .map(|s| s.to_string())
-                            .collect();
+    fn parse_unary(&mut self) -> Result<Query, ParseError> {
+        match self.peek() {
+            Some((_, Token::Not)) => {
+                self.next += 1;
+                Ok(Query::Not(Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, ParseError> {
+        let (position, token) = match self.tokens.get(self.next) {
+            Some((position, token)) => (*position, token.clone()),
+            None => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::MissingOperand,
+                    position: self.end_position(),
+                })
+            }
+        };
+        self.next += 1;
+
+        match token {
+            Token::Word(word) => Ok(Query::Term(word)),
+            Token::Phrase(mut words) if words.len() == 1 => Ok(Query::Term(words.pop().unwrap().1)),
+            Token::Phrase(words) => Ok(Query::Phrase(words)),
+            Token::Site(host) => Ok(Query::Site(host)),
+            Token::Title(words) => Ok(Query::Title(words)),
+            Token::Author(words) => Ok(Query::Author(words)),
+            Token::Tag(tag) => Ok(Query::Tag(tag)),
+            Token::After(secs) => Ok(Query::After(secs)),
+            Token::Before(secs) => Ok(Query::Before(secs)),
+            Token::OpenParen => {
+                let query = self.parse_or()?;
+                match self.peek() {
+                    Some((_, Token::CloseParen)) => {
+                        self.next += 1;
+                        Ok(query)
                     }
-                    None => ngram_result_set.clear(),
+                    _ => Err(ParseError {
+                        kind: ParseErrorKind::UnmatchedOpenParen,
+                        position,
+                    }),
                 }
             }
-            Some(ngram_result_set)
+            Token::CloseParen => Err(ParseError {
+                kind: ParseErrorKind::UnmatchedCloseParen,
+                position,
+            }),
+            Token::And | Token::Or | Token::Not => Err(ParseError {
+                kind: ParseErrorKind::MissingOperand,
+                position,
+            }),
         }
-    };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::StandardAnalyzer;
+
+    fn parse(query_str: &str) -> Result<Query, ParseError> {
+        super::parse(query_str, &StandardAnalyzer::plain())
+    }
+
+    fn term(s: &str) -> Query {
+        Query::Term(s.to_string())
+    }
 
-    match (query.exact_ngram.is_some(), query.unigrams.is_some()) {
-        // The query is only asking for an exact string search.
-        (true, false) => exact_results,
+    fn phrase(words: &[&str]) -> Query {
+        Query::Phrase((0..).zip(words.iter().map(|w| w.to_string())).collect())
+    }
+
+    fn error(query_str: &str) -> (ParseErrorKind, usize) {
+        let e = parse(query_str).unwrap_err();
+        (e.kind, e.position)
+    }
+
+    #[test]
+    fn parses_boolean_operators_with_precedence() {
+        assert_eq!(
+            parse("Cache OR caching invalidation -javascript").unwrap(),
+            Query::Or(vec![
+                term("cache"),
+                Query::And(vec![
+                    term("caching"),
+                    term("invalidation"),
+                    Query::Not(Box::new(term("javascript"))),
+                ]),
+            ])
+        );
+        assert_eq!(
+            parse("(cache OR caching) AND NOT \"tail at scale\"").unwrap(),
+            Query::And(vec![
+                Query::Or(vec![term("cache"), term("caching")]),
+                Query::Not(Box::new(phrase(&["tail", "at", "scale"]))),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_fields_and_multiple_phrases() {
+        assert_eq!(
+            parse("\"tail latency\" site:DanLuu.com -site:jm.dev title:\"the tail\" \"at scale\"")
+                .unwrap(),
+            Query::And(vec![
+                phrase(&["tail", "latency"]),
+                Query::Site("danluu.com".into()),
+                Query::Not(Box::new(Query::Site("jm.dev".into()))),
+                Query::Title(vec!["the".into(), "tail".into()]),
+                phrase(&["at", "scale"]),
+            ])
+        );
+        assert_eq!(parse("tail-latency").unwrap(), phrase(&["tail", "latency"]));
+        assert_eq!(
+            parse("author:\"Marc Brooker\" tag:Architecture after:2019 before:2021-06").unwrap(),
+            Query::And(vec![
+                Query::Author(vec!["marc".into(), "brooker".into()]),
+                Query::Tag("architecture".into()),
+                Query::After(1_546_300_800),
+                Query::Before(1_622_505_600),
+            ])
+        );
+    }
 
-        // The query only wants to match N unigrams.
-        (false, true) => Some(unigram_result_set),
+    #[test]
+    fn reports_where_parsing_failed() {
+        assert_eq!(error(""), (ParseErrorKind::EmptyQuery, 0));
+        assert_eq!(error("a \"b c"), (ParseErrorKind::UnterminatedPhrase, 2));
+        assert_eq!(error("a \"  \""), (ParseErrorKind::EmptyPhrase, 2));
+        assert_eq!(error("(a OR b"), (ParseErrorKind::UnmatchedOpenParen, 0));
+        assert_eq!(error("a) b"), (ParseErrorKind::UnmatchedCloseParen, 1));
+        assert_eq!(error("a OR"), (ParseErrorKind::MissingOperand, 2));
+        assert_eq!(error("a site: b"), (ParseErrorKind::MissingFieldValue, 2));
+        assert_eq!(error("a after:2021-13"), (ParseErrorKind::InvalidDate, 2));
+    }
+
+    #[test]
+    fn analyzes_words_like_the_index() {
+        let analyzer = StandardAnalyzer::default();
+        assert_eq!(
+            super::parse("The -the Caches, title:\"of latency\"", &analyzer).unwrap(),
+            Query::And(vec![term("cach"), Query::Title(vec!["latenc".into()])])
+        );
+        assert_eq!(
+            super::parse("the", &analyzer).unwrap_err().kind,
+            ParseErrorKind::EmptyQuery
+        );
+        assert_eq!(
+            super::parse("\"tail at scale\"", &analyzer).unwrap(),
+            Query::Phrase(vec![(0, "tail".into()), (2, "scale".into())])
+        );
+    }
+
+    #[test]
+    fn phrases_keep_the_gaps_stop_words_leave() {
+        let mut index = Index::default();
+        for (url, text) in [
+            ("exact", "The tail at scale"),
+            ("other stop word", "a tail of scale"),
+            ("longer gap", "tail of the scale"),
+            ("no gap", "tail scale"),
+        ] {
+            index.index_document(crate::net::SearchableDocument {
+                url: url.into(),
+                searchable_texts: vec![text.into()],
+                ..Default::default()
+            });
+        }
+
+        let mut urls: Vec<String> = query("\"tail at scale\"".into(), &index)
+            .unwrap()
+            .into_iter()
+            .map(|(url, _)| url)
+            .collect();
+        urls.sort();
+        assert_eq!(urls, ["exact", "other stop word"]);
+    }
+
+    #[test]
+    fn evaluates_against_the_index() {
+        let mut index = Index::default();
+        for (url, title, text) in [
+            ("https://danluu.com/a", "Latency", "cache latency"),
+            ("https://www.danluu.com/b", "Caching", "caching throughput"),
+            ("https://jm.dev/c", "Latency", "cache throughput"),
+        ] {
+            index.index_document(crate::net::SearchableDocument {
+                url: url.into(),
+                title: title.into(),
+                searchable_texts: vec![text.into()],
+                ..Default::default()
+            });
+        }
+
+        let urls = |q: &str| -> Vec<String> {
+            let mut urls: Vec<String> = query(q.into(), &index)
+                .unwrap()
+                .into_iter()
+                .map(|(url, _)| url)
+                .collect();
+            urls.sort();
+            urls
+        };
+
+        assert_eq!(
+            urls("cache OR caching"),
+            [
+                "https://danluu.com/a",
+                "https://jm.dev/c",
+                "https://www.danluu.com/b"
+            ]
+        );
+        assert_eq!(
+            urls("throughput -site:jm.dev"),
+            ["https://www.danluu.com/b"]
+        );
+        assert_eq!(
+            urls("title:latency cache"),
+            ["https://danluu.com/a", "https://jm.dev/c"]
+        );
+        assert_eq!(urls("-throughput"), ["https://danluu.com/a"]);
+    }
+
+    #[test]
+    fn filters_by_author_tag_and_date() {
+        let mut index = Index::default();
+        for (url, author, tag, published) in [
+            ("timeouts", "Marc Brooker", "Resilience", "2019-08-01"),
+            ("jitter", "Marc Brooker", "Architecture", "2015-03-04"),
+            ("caching", "Matt Brinkley", "Architecture", "2020-01-15"),
+        ] {
+            index.index_document(crate::net::SearchableDocument {
+                url: url.into(),
+                author: Some(author.into()),
+                tags: vec![tag.into()],
+                published_at_linux_epoch_secs: crate::discovery::parse_date(published),
+                searchable_texts: vec!["Retries and backoff.".into()],
+                ..Default::default()
+            });
+        }
 
-        // The query is meaningless.
-        (false, false) => None,
+        let urls = |q: &str| -> Vec<String> {
+            let mut urls: Vec<String> = query(q.into(), &index)
+                .unwrap()
+                .into_iter()
+                .map(|(url, _)| url)
+                .collect();
+            urls.sort();
+            urls
+        };
 
-        // The query wants the intersection of an exact ngram search and N unigrams AND'd.
-        (true, true) => Some(
-            unigram_result_set
-                .intersection(&exact_results.unwrap())
-                .map(|s| s.to_string())
-                .collect(),
-        ),
+        assert_eq!(urls("retries author:brooker"), ["jitter", "timeouts"]);
+        assert_eq!(urls("tag:architecture -author:brooker"), ["caching"]);
+        assert_eq!(urls("retries after:2019-08"), ["caching", "timeouts"]);
+        assert_eq!(urls("retries before:2019-08-01"), ["jitter"]);
+        // The author's name is searchable on its own, too.
+        assert_eq!(urls("brinkley"), ["caching"]);
     }
 }
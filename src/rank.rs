@@ -0,0 +1,103 @@
+use crate::index::Index;
+use roaring::RoaringBitmap;
+
+/// Okapi BM25, the classic bag-of-words relevance function.
+///
+/// A document scores higher the more often it mentions rare query terms,
+/// normalized by its length, so a long page doesn't win just by being long.
+pub struct Bm25 {
+    /// How quickly repeated occurrences of a term stop adding to the score.
+    pub k1: f32,
+
+    /// How strongly to penalize documents that are longer than average.
+    /// 0 disables length normalization, 1 applies it fully.
+    pub b: f32,
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Bm25 { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Bm25 {
+    /// Scores every candidate document against `terms`, returning the document
+    /// URLs ordered from most to least relevant.
+    pub fn rank(
+        &self,
+        index: &Index,
+        terms: &[String],
+        candidates: &RoaringBitmap,
+    ) -> Vec<(String, f32)> {
+        let document_count = index.document_lengths.len() as f32;
+        let average_length = index
+            .document_lengths
+            .iter()
+            .map(|l| *l as f32)
+            .sum::<f32>()
+            / document_count.max(1.0);
+
+        // The candidates come out of the bitmap in increasing order, so each
+        // term's postings are read in a single forward pass.
+        let mut postings: Vec<_> = terms
+            .iter()
+            .filter_map(|term| index.unigram_postings(term))
+            .map(|postings| {
+                let n = postings.documents().len() as f32;
+                let idf = (1.0 + (document_count - n + 0.5) / (n + 0.5)).ln();
+                (idf, postings.cursor())
+            })
+            .collect();
+
+        let mut results: Vec<(String, f32)> = candidates
+            .iter()
+            .map(|document| {
+                let length = index.document_lengths[document as usize] as f32;
+                let norm = self.k1 * (1.0 - self.b + self.b * length / average_length.max(1.0));
+                let score = postings
+                    .iter_mut()
+                    .filter_map(|(idf, cursor)| {
+                        let tf = cursor.seek(document)?.frequency() as f32;
+                        Some(*idf * tf * (self.k1 + 1.0) / (tf + norm))
+                    })
+                    .sum();
+
+                (index.document_url(document).to_string(), score)
+            })
+            .collect();
+
+        // Ties are broken by URL, so the same query always pages the same way.
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{Analyzer, StandardAnalyzer};
+
+    fn texts(texts: &[&str]) -> Vec<Vec<String>> {
+        texts
+            .iter()
+            .map(|t| StandardAnalyzer::plain().analyze(t))
+            .collect()
+    }
+
+    #[test]
+    fn more_frequent_terms_in_shorter_documents_rank_first() {
+        let mut index = Index::default();
+        index.index_texts(
+            "long".into(),
+            texts(&["latency", "a b c d e f g h i j k l m n o p"]),
+        );
+        index.index_texts("short".into(), texts(&["latency latency"]));
+        index.index_texts("other".into(), texts(&["throughput"]));
+
+        let ranked = Bm25::default().rank(&index, &["latency".to_string()], &(0..2).collect());
+
+        assert_eq!(ranked[0].0, "short");
+        assert_eq!(ranked[1].0, "long");
+        assert!(ranked[0].1 > ranked[1].1);
+    }
+}
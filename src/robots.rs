@@ -0,0 +1,466 @@
+use crate::net::MAX_REDIRECTS;
+use crate::scheduler::{self, Scheduler};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// How long a robots.txt we couldn't get (a network error, a 5xx or a 429)
+/// keeps its host off limits before we ask for it again.
+pub const RETRY_UNREACHABLE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// The rules a site's robots.txt sets for our user agent.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RobotsTxt {
+    rules: Vec<Rule>,
+
+    /// How long the site asks crawlers to wait between requests.
+    pub crawl_delay: Option<Duration>,
+
+    /// The sitemaps the robots.txt points to. These apply to every user agent.
+    pub sitemaps: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl RobotsTxt {
+    /// Everything may be crawled. This is what a missing robots.txt means.
+    pub fn allow_all() -> Self {
+        RobotsTxt::default()
+    }
+
+    /// Nothing may be crawled. This is what an unreachable robots.txt means,
+    /// for as long as it's unreachable.
+    pub fn disallow_all() -> Self {
+        RobotsTxt {
+            rules: vec![Rule {
+                allow: false,
+                pattern: "/".to_string(),
+            }],
+            ..RobotsTxt::default()
+        }
+    }
+
+    /// Parses a robots.txt body, keeping only the group that applies to
+    /// `product_token` (e.g. "folklore"), or else the `*` group.
+    pub fn parse(body: &str, product_token: &str) -> Self {
+        let product_token = product_token.to_lowercase();
+
+        // Each group is the user agents it names, and the rules and delay under them.
+        let mut groups: Vec<(Vec<String>, RobotsTxt)> = vec![];
+        let mut in_agent_lines = false;
+        let mut sitemaps = vec![];
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+
+            if key == "sitemap" {
+                sitemaps.push(value.to_string());
+                continue;
+            }
+
+            if key == "user-agent" {
+                if !in_agent_lines {
+                    groups.push((vec![], RobotsTxt::default()));
+                }
+                in_agent_lines = true;
+                let agent = value.split('/').next().unwrap_or("").trim().to_lowercase();
+                groups.last_mut().unwrap().0.push(agent);
+                continue;
+            }
+
+            in_agent_lines = false;
+            let group = match groups.last_mut() {
+                Some((_, group)) => group,
+                // Rules before any user-agent line don't apply to anyone.
+                None => continue,
+            };
+
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => group.rules.push(Rule {
+                    allow: key == "allow",
+                    pattern: value.to_string(),
+                }),
+                "crawl-delay" => {
+                    group.crawl_delay = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                        .map(Duration::from_secs_f64)
+                }
+                _ => {}
+            }
+        }
+
+        let matching = |agent: &str| {
+            groups
+                .iter()
+                .filter(|(agents, _)| agents.iter().any(|a| a == agent))
+                .map(|(_, group)| group.clone())
+                .reduce(|mut all, group| {
+                    all.rules.extend(group.rules);
+                    all.crawl_delay = all.crawl_delay.or(group.crawl_delay);
+                    all
+                })
+        };
+
+        RobotsTxt {
+            sitemaps,
+            ..matching(&product_token)
+                .or_else(|| matching("*"))
+                .unwrap_or_default()
+        }
+    }
+
+    /// Whether `url` may be crawled. The most specific (longest) matching rule
+    /// wins, and `Allow` wins a tie.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        self.rules
+            .iter()
+            .filter(|rule| pattern_matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map_or(true, |rule| rule.allow)
+    }
+}
+
+/// Matches a robots.txt path pattern, where `*` matches any run of characters
+/// and a trailing `$` anchors the pattern to the end of the path.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match path.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        // The last part of an anchored pattern has to sit at the very end.
+        if anchored && i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// Fetches each host's robots.txt once, and remembers its rules. One we
+/// couldn't get is asked for again after `RETRY_UNREACHABLE_AFTER`.
+pub struct RobotsCache {
+    product_token: String,
+    retry_unreachable_after: Duration,
+    hosts: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<Cached>>>>>,
+}
+
+struct Cached {
+    robots: RobotsTxt,
+
+    /// When to fetch the robots.txt again, if it was unreachable.
+    expires_at: Option<Instant>,
+}
+
+impl RobotsCache {
+    /// `user_agent` is the full user agent we crawl with. Its product token
+    /// (the part before the first `/`) selects the robots.txt group we obey.
+    pub fn new(user_agent: &str) -> Self {
+        RobotsCache {
+            product_token: user_agent
+                .split('/')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_string(),
+            retry_unreachable_after: RETRY_UNREACHABLE_AFTER,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The robots.txt rules for the origin of `url`. Fetching them waits on
+    /// `scheduler`, like any other request to the host.
+    pub async fn get(
+        &self,
+        client: &reqwest::Client,
+        scheduler: &Scheduler,
+        url: &Url,
+    ) -> RobotsTxt {
+        let origin = url.origin().ascii_serialization();
+        let cell = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(origin.clone())
+            .or_default()
+            .clone();
+
+        // Held across the fetch, so concurrent callers wait for one fetch.
+        let mut cached = cell.lock().await;
+        if let Some(cached) = cached.as_ref() {
+            if cached.expires_at.map_or(true, |at| Instant::now() < at) {
+                return cached.robots.clone();
+            }
+        }
+
+        let fetched = match self.fetch(client, scheduler, &origin).await {
+            Ok(robots) => {
+                println!("Loaded robots.txt for {}: {:?}", origin, robots);
+                Cached {
+                    robots,
+                    expires_at: None,
+                }
+            }
+            Err(reason) => {
+                eprintln!(
+                    "Couldn't get robots.txt for {} ({}); not crawling it for {:?}.",
+                    origin, reason, self.retry_unreachable_after
+                );
+                Cached {
+                    robots: RobotsTxt::disallow_all(),
+                    expires_at: Some(Instant::now() + self.retry_unreachable_after),
+                }
+            }
+        };
+        let robots = fetched.robots.clone();
+        *cached = Some(fetched);
+        robots
+    }
+
+    pub async fn is_allowed(
+        &self,
+        client: &reqwest::Client,
+        scheduler: &Scheduler,
+        url: &Url,
+    ) -> bool {
+        self.get(client, scheduler, url).await.is_allowed(url)
+    }
+
+    /// The host's robots.txt, or why we couldn't get it this time.
+    async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        scheduler: &Scheduler,
+        origin: &str,
+    ) -> Result<RobotsTxt, String> {
+        let mut url = match Url::parse(origin).and_then(|o| o.join("/robots.txt")) {
+            Ok(url) => url,
+            Err(_) => return Ok(RobotsTxt::allow_all()),
+        };
+
+        for _ in 0..=MAX_REDIRECTS {
+            let _permit = scheduler.acquire(&url).await;
+            let resp = client
+                .get(url.clone())
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            let status = resp.status();
+            scheduler.record(&url, status, scheduler::retry_after(&resp));
+            if status.is_redirection() {
+                match resp
+                    .headers()
+                    .get(reqwest::header::LOCATION)
+                    .and_then(|l| l.to_str().ok())
+                    .and_then(|l| url.join(l).ok())
+                {
+                    Some(location) => {
+                        url = location;
+                        continue;
+                    }
+                    None => return Ok(RobotsTxt::allow_all()),
+                }
+            }
+
+            return if status.is_success() {
+                let body = resp.text().await.map_err(|e| e.to_string())?;
+                Ok(RobotsTxt::parse(&body, &self.product_token))
+            } else if status.is_client_error() && !scheduler::is_throttled(status) {
+                Ok(RobotsTxt::allow_all())
+            } else {
+                Err(format!("status {}", status))
+            };
+        }
+
+        Ok(RobotsTxt::allow_all())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const ROBOTS_TXT: &str = "
+        # Comments are ignored.
+        User-agent: *
+        Disallow: /
+
+        User-agent: Folklore
+        User-agent: otherbot
+        Disallow: /private
+        Allow: /private/public
+        Disallow: /*.php$
+        Disallow: /search?
+        Crawl-delay: 2.5
+
+        Sitemap: https://example.com/sitemap.xml
+    ";
+
+    fn url(path: &str) -> Url {
+        Url::parse("https://example.com")
+            .unwrap()
+            .join(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn applies_the_most_specific_rule_for_our_agent() {
+        let robots = RobotsTxt::parse(ROBOTS_TXT, "folklore");
+
+        for (path, allowed) in [
+            ("/", true),
+            ("/blog/post", true),
+            ("/private", false),
+            ("/private/notes", false),
+            ("/private/public/notes", true),
+            ("/index.php", false),
+            ("/index.php?page=2", true),
+            ("/search", true),
+            ("/search?q=latency", false),
+        ] {
+            assert_eq!(robots.is_allowed(&url(path)), allowed, "{}", path);
+        }
+        assert_eq!(robots.crawl_delay, Some(Duration::from_millis(2500)));
+        assert_eq!(robots.sitemaps, vec!["https://example.com/sitemap.xml"]);
+    }
+
+    #[test]
+    fn falls_back_to_the_wildcard_group() {
+        let robots = RobotsTxt::parse(ROBOTS_TXT, "somebot");
+        assert!(!robots.is_allowed(&url("/blog/post")));
+        assert_eq!(robots.crawl_delay, None);
+
+        assert!(RobotsTxt::parse("", "folklore").is_allowed(&url("/")));
+    }
+
+    #[tokio::test]
+    async fn fetches_robots_txt_once_per_host() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/robots.txt",
+            get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                ROBOTS_TXT
+            }),
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://localhost:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let client = reqwest::Client::new();
+        let scheduler = Scheduler::new(1, &[]);
+        let cache = RobotsCache::new("folklore/0.1 (+https://folklore.dev)");
+        let page = |path: &str| Url::parse(&format!("{}{}", base, path)).unwrap();
+
+        assert!(
+            cache
+                .is_allowed(&client, &scheduler, &page("/blog/post"))
+                .await
+        );
+        assert!(
+            !cache
+                .is_allowed(&client, &scheduler, &page("/private/notes"))
+                .await
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_an_unreachable_robots_txt() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/robots.txt",
+            get(move || async move {
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => (StatusCode::SERVICE_UNAVAILABLE, ""),
+                    _ => (StatusCode::OK, ROBOTS_TXT),
+                }
+            }),
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://localhost:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let client = reqwest::Client::new();
+        let scheduler = Scheduler::new(1, &[]);
+        let post = Url::parse(&format!("{}/blog/post", base)).unwrap();
+
+        // A 5xx keeps the host off limits, but only for a while.
+        let cache = RobotsCache::new("folklore");
+        assert!(!cache.is_allowed(&client, &scheduler, &post).await);
+        assert!(!cache.is_allowed(&client, &scheduler, &post).await);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        let cache = RobotsCache {
+            retry_unreachable_after: Duration::ZERO,
+            ..RobotsCache::new("folklore")
+        };
+        assert!(!cache.is_allowed(&client, &scheduler, &post).await);
+        assert!(cache.is_allowed(&client, &scheduler, &post).await);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn missing_robots_txt_allows_everything() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://localhost:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(axum::Router::new().into_make_service()),
+        );
+
+        let cache = RobotsCache::new("folklore");
+        let url = Url::parse(&format!("{}/anything", base)).unwrap();
+        assert!(
+            cache
+                .is_allowed(&reqwest::Client::new(), &Scheduler::new(1, &[]), &url)
+                .await
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::index::host_key;
+use crate::Website;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Instant};
+use url::Url;
+
+gflags::define! {
+    /// The most fetches the crawler has in flight at once, across all hosts.
+    pub --max_concurrent_fetches <N>: usize = 16
+}
+
+/// The first backoff after a host throttles us without a `Retry-After`.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// We never back off from a host for longer than this in one go.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// The longest robots.txt Crawl-delay we honour. Longer ones would stall the
+/// crawl of that host for good.
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+/// How hard we may hit a single host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Politeness {
+    /// The minimum time between the starts of two requests to the host.
+    pub min_delay: Duration,
+
+    /// How many requests to the host may be in flight at once.
+    pub max_connections: usize,
+}
+
+impl Default for Politeness {
+    fn default() -> Self {
+        Politeness {
+            min_delay: Duration::from_millis(500),
+            max_connections: 2,
+        }
+    }
+}
+
+impl Politeness {
+    /// The politeness configured for `website`, falling back to the defaults.
+    pub fn for_website(website: &Website) -> Self {
+        let default = Politeness::default();
+        Politeness {
+            min_delay: website
+                .crawl_delay_ms
+                .map_or(default.min_delay, Duration::from_millis),
+            max_connections: website
+                .max_connections
+                .unwrap_or(default.max_connections)
+                .max(1),
+        }
+    }
+
+    /// The stricter of two politeness settings, for hosts shared by several websites.
+    fn strictest(self, other: Politeness) -> Self {
+        Politeness {
+            min_delay: self.min_delay.max(other.min_delay),
+            max_connections: self.max_connections.min(other.max_connections),
+        }
+    }
+}
+
+/// Hands out permission to fetch, so that no host sees requests closer
+/// together than its delay, or more of them in flight than its connection cap,
+/// and the crawl as a whole stays under a global concurrency limit.
+pub struct Scheduler {
+    global: Arc<Semaphore>,
+    configured: HashMap<String, Politeness>,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+struct Host {
+    connections: Arc<Semaphore>,
+    state: Mutex<HostState>,
+}
+
+struct HostState {
+    min_delay: Duration,
+
+    /// The host's robots.txt Crawl-delay, if it has one.
+    crawl_delay: Option<Duration>,
+
+    /// When the next request to the host may start.
+    next_at: Instant,
+
+    /// How long we last backed off for; zero while the host is happy.
+    backoff: Duration,
+}
+
+/// Held for the duration of a fetch. Dropping it frees up the connection.
+pub struct Permit {
+    _connection: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+impl Scheduler {
+    pub fn new(max_concurrent_fetches: usize, websites: &[Website]) -> Self {
+        let mut configured: HashMap<String, Politeness> = HashMap::new();
+        for website in websites {
+            let host = match Url::parse(&website.url).ok().and_then(|u| host(&u)) {
+                Some(host) => host,
+                None => continue,
+            };
+            let politeness = Politeness::for_website(website);
+            configured
+                .entry(host)
+                .and_modify(|p| *p = p.strictest(politeness))
+                .or_insert(politeness);
+        }
+
+        Scheduler {
+            global: Arc::new(Semaphore::new(max_concurrent_fetches.max(1))),
+            configured,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until `url` may be fetched.
+    pub async fn acquire(&self, url: &Url) -> Permit {
+        let host = self.host(url);
+        let connection = host.connections.clone().acquire_owned().await.unwrap();
+        // Taken before the host's slot is reserved, so waiting for it can't
+        // eat into the delay before the host's next request.
+        let global = self.global.clone().acquire_owned().await.unwrap();
+
+        loop {
+            let wait_until = {
+                let mut state = host.state.lock().unwrap();
+                let now = Instant::now();
+                if state.next_at <= now {
+                    state.next_at = now + state.delay();
+                    None
+                } else {
+                    Some(state.next_at)
+                }
+            };
+
+            match wait_until {
+                Some(at) => time::sleep_until(at).await,
+                None => break,
+            }
+        }
+
+        Permit {
+            _connection: connection,
+            _global: global,
+        }
+    }
+
+    /// Honours a Crawl-delay from the host's robots.txt, if it's longer than
+    /// the configured delay, up to `MAX_CRAWL_DELAY`.
+    pub fn set_crawl_delay(&self, url: &Url, crawl_delay: Option<Duration>) {
+        self.host(url).state.lock().unwrap().crawl_delay =
+            crawl_delay.map(|delay| delay.min(MAX_CRAWL_DELAY));
+    }
+
+    /// Records the outcome of a fetch. A 429 or 503 pushes the host's next
+    /// request back by its `Retry-After`, or by an exponentially growing backoff.
+    pub fn record(&self, url: &Url, status: reqwest::StatusCode, retry_after: Option<Duration>) {
+        let host = self.host(url);
+        let mut state = host.state.lock().unwrap();
+
+        if !is_throttled(status) {
+            state.backoff = Duration::ZERO;
+            return;
+        }
+
+        state.backoff = (state.backoff * 2).clamp(INITIAL_BACKOFF, MAX_BACKOFF);
+        let wait = retry_after.unwrap_or(state.backoff).min(MAX_BACKOFF);
+        state.next_at = state.next_at.max(Instant::now() + wait);
+        println!(
+            "{} throttled us ({}), backing off for {:?}",
+            url, status, wait
+        );
+    }
+
+    fn host(&self, url: &Url) -> Arc<Host> {
+        let key = host(url).unwrap_or_default();
+        let politeness = self.configured.get(&key).copied().unwrap_or_default();
+
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    connections: Arc::new(Semaphore::new(politeness.max_connections)),
+                    state: Mutex::new(HostState {
+                        min_delay: politeness.min_delay,
+                        crawl_delay: None,
+                        next_at: Instant::now(),
+                        backoff: Duration::ZERO,
+                    }),
+                })
+            })
+            .clone()
+    }
+}
+
+impl HostState {
+    fn delay(&self) -> Duration {
+        self.min_delay.max(self.crawl_delay.unwrap_or_default())
+    }
+}
+
+/// Hosts are keyed like `index::host_key`, so `www.` and the apex domain share
+/// one politeness slot.
+fn host(url: &Url) -> Option<String> {
+    url.host_str().map(host_key)
+}
+
+/// Whether the server is asking us to slow down.
+pub fn is_throttled(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+pub fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    let value = resp
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn website(url: &str, crawl_delay_ms: Option<u64>, max_connections: Option<usize>) -> Website {
+        Website {
+            crawl_delay_ms,
+            max_connections,
+            ..Website::new(url)
+        }
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_out_requests_to_the_same_host() {
+        let scheduler = Scheduler::new(
+            8,
+            &[website("https://slow.example/blog", Some(1000), Some(4))],
+        );
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            drop(scheduler.acquire(&url("https://slow.example/post")).await);
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        // Other hosts aren't held up, and get the default delay.
+        drop(scheduler.acquire(&url("https://fast.example/")).await);
+        drop(scheduler.acquire(&url("https://fast.example/")).await);
+        assert_eq!(
+            start.elapsed(),
+            Duration::from_secs(2) + Politeness::default().min_delay
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shares_www_hosts_and_caps_crawl_delays() {
+        let scheduler = Scheduler::new(8, &[website("https://slow.example", Some(1000), None)]);
+        let start = Instant::now();
+
+        drop(scheduler.acquire(&url("https://slow.example/a")).await);
+        drop(scheduler.acquire(&url("https://www.slow.example/b")).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        scheduler.set_crawl_delay(
+            &url("https://www.slow.example/"),
+            Some(Duration::from_secs(86400)),
+        );
+        drop(scheduler.acquire(&url("https://slow.example/c")).await);
+        drop(scheduler.acquire(&url("https://slow.example/d")).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(2) + MAX_CRAWL_DELAY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waiting_for_the_global_limit_keeps_hosts_spaced_out() {
+        let scheduler = Scheduler::new(1, &[website("https://slow.example", Some(1000), None)]);
+        let start = Instant::now();
+
+        let held = scheduler.acquire(&url("https://other.example/")).await;
+        let first = async {
+            drop(scheduler.acquire(&url("https://slow.example/a")).await);
+            start.elapsed()
+        };
+        let release = async {
+            time::sleep(Duration::from_secs(5)).await;
+            drop(held);
+        };
+        assert_eq!(tokio::join!(first, release).0, Duration::from_secs(5));
+
+        drop(scheduler.acquire(&url("https://slow.example/b")).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(6));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_concurrent_connections_per_host() {
+        let scheduler = Scheduler::new(8, &[website("https://one.example", Some(0), Some(1))]);
+
+        let page = url("https://one.example/a");
+
+        let held = scheduler.acquire(&page).await;
+        let waiting = time::timeout(Duration::from_secs(60), scheduler.acquire(&page));
+        assert!(waiting.await.is_err());
+
+        drop(held);
+        let _permit = scheduler.acquire(&page).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_when_throttled() {
+        let scheduler = Scheduler::new(8, &[website("https://busy.example", Some(0), None)]);
+        let busy = url("https://busy.example/");
+        let start = Instant::now();
+
+        scheduler.record(
+            &busy,
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(30)),
+        );
+        drop(scheduler.acquire(&busy).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+
+        // Without a Retry-After, the backoff doubles each time.
+        scheduler.record(&busy, reqwest::StatusCode::SERVICE_UNAVAILABLE, None);
+        scheduler.record(&busy, reqwest::StatusCode::SERVICE_UNAVAILABLE, None);
+        drop(scheduler.acquire(&busy).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(34));
+
+        scheduler.record(&busy, reqwest::StatusCode::OK, None);
+        drop(scheduler.acquire(&busy).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(34));
+    }
+}
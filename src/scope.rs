@@ -0,0 +1,150 @@
+use crate::index::host_key;
+use crate::Website;
+use url::{Host, Url};
+
+/// Decides which URLs the crawler may touch: those on the hosts of the
+/// configured websites.
+///
+/// - `www.example.com` and `example.com` are the same site.
+/// - Subdomains are only in scope for websites with `include_subdomains`.
+/// - Only http(s) URLs are in scope, and http links to a website configured
+///   with https are upgraded.
+/// - IP-address hosts must match exactly. Ports are ignored throughout.
+#[derive(Debug, Default)]
+pub struct ScopePolicy {
+    sites: Vec<Site>,
+}
+
+#[derive(Debug)]
+struct Site {
+    /// A `host_key` for domains, or the address itself for IP hosts.
+    key: String,
+    is_domain: bool,
+    include_subdomains: bool,
+    https: bool,
+}
+
+impl ScopePolicy {
+    pub fn new(websites: &[Website]) -> Self {
+        let mut policy = ScopePolicy::default();
+        for website in websites {
+            match Url::parse(&website.url) {
+                Ok(url) => policy.allow(&url, website.include_subdomains),
+                Err(e) => eprintln!("Skipping website {}: {}", website.url, e),
+            }
+        }
+        policy
+    }
+
+    /// Brings `url`'s host into scope.
+    pub fn allow(&mut self, url: &Url, include_subdomains: bool) {
+        if let Some((key, is_domain)) = site_key(url) {
+            self.sites.push(Site {
+                key,
+                is_domain,
+                include_subdomains: include_subdomains && is_domain,
+                https: url.scheme() == "https",
+            });
+        }
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.site(url).is_some()
+    }
+
+    /// `url` as the crawler should request it, or None if it's out of scope.
+    pub fn canonicalize(&self, url: &Url) -> Option<Url> {
+        let site = self.site(url)?;
+        let mut url = url.clone();
+        if site.https && url.scheme() == "http" {
+            url.set_scheme("https").ok()?;
+            // An explicit :80 makes no sense for https; a custom port is the site's business.
+            if url.port() == Some(80) {
+                url.set_port(None).ok()?;
+            }
+        }
+        Some(url)
+    }
+
+    fn site(&self, url: &Url) -> Option<&Site> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        let (key, is_domain) = site_key(url)?;
+        self.sites.iter().find(|site| {
+            site.is_domain == is_domain
+                && (site.key == key
+                    || (site.include_subdomains && key.ends_with(&format!(".{}", site.key))))
+        })
+    }
+}
+
+fn site_key(url: &Url) -> Option<(String, bool)> {
+    match url.host()? {
+        Host::Domain(domain) => Some((host_key(domain.trim_end_matches('.')), true)),
+        Host::Ipv4(ip) => Some((ip.to_string(), false)),
+        Host::Ipv6(ip) => Some((ip.to_string(), false)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_urls() {
+        let blog = Website {
+            include_subdomains: true,
+            ..Website::new("https://blog.example.com")
+        };
+        let policy = ScopePolicy::new(&[
+            Website::new("https://danluu.com"),
+            Website::new("http://www.brendangregg.com/blog"),
+            blog,
+            Website::new("http://127.0.0.1:8080"),
+            Website::new("http://[::1]/"),
+        ]);
+
+        for (url, expected) in [
+            ("https://danluu.com/x", Some("https://danluu.com/x")),
+            ("http://danluu.com/x", Some("https://danluu.com/x")),
+            ("http://danluu.com:80/x", Some("https://danluu.com/x")),
+            ("http://WWW.danluu.com/x", Some("https://www.danluu.com/x")),
+            ("https://sub.danluu.com/x", None),
+            ("https://evildanluu.com/x", None),
+            (
+                "http://brendangregg.com/blog",
+                Some("http://brendangregg.com/blog"),
+            ),
+            (
+                "https://www.brendangregg.com/",
+                Some("https://www.brendangregg.com/"),
+            ),
+            (
+                "https://blog.example.com/",
+                Some("https://blog.example.com/"),
+            ),
+            (
+                "https://a.b.blog.example.com/",
+                Some("https://a.b.blog.example.com/"),
+            ),
+            ("https://notblog.example.com/", None),
+            ("https://example.com/", None),
+            ("http://127.0.0.1:9999/x", Some("http://127.0.0.1:9999/x")),
+            ("http://127.0.0.2/", None),
+            ("http://[::1]/x", Some("http://[::1]/x")),
+            ("ftp://danluu.com/x", None),
+            ("mailto:dan@danluu.com", None),
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(
+                policy.canonicalize(&url).as_ref().map(Url::as_str),
+                expected,
+                "{}",
+                url
+            );
+            assert_eq!(policy.contains(&url), expected.is_some(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use crate::analysis::{Analyzer, StandardAnalyzer};
+use crate::index::Index;
+use crate::net;
+use crate::query;
+use crate::Website;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::sync::Arc;
+use url::Url;
+
+/// How many results we return per page of search results.
+pub const PAGE_SIZE: usize = 20;
+
+/// Snippets are cut down to roughly this many characters.
+const SNIPPET_LEN: usize = 240;
+
+/// Everything a request handler needs to answer a search.
+pub struct SearchState {
+    pub index: Index,
+
+    /// Where the crawled `SearchableDocument`s live. We read titles and
+    /// snippets from here, so the index itself stays small.
+    pub output_dir: PathBuf,
+
+    /// The configured websites, for reporting per-site coverage.
+    pub websites: Vec<Website>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default)]
+    pub sort: Sort,
+}
+
+/// How search results are ordered.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// Most relevant first, by BM25.
+    #[default]
+    Relevance,
+
+    /// Most recently published first. Documents we have no date for come
+    /// last, still in order of relevance.
+    Newest,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchHit {
+    pub url: String,
+    pub score: f32,
+    pub title: String,
+    pub fetched_at_linux_epoch_secs: u64,
+    pub snippet: String,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub published_at_linux_epoch_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResponse {
+    pub query: String,
+    pub page: usize,
+    #[serde(default)]
+    pub sort: Sort,
+    pub total_hits: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+pub fn router(state: Arc<SearchState>) -> Router {
+    Router::new()
+        .route("/", get(html_search))
+        .route("/search", get(json_search))
+        .route("/sites", get(json_sites))
+        .with_state(state)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SiteCount {
+    pub url: String,
+    pub documents: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub error: String,
+
+    /// The byte offset into the query where parsing failed.
+    pub position: usize,
+}
+
+async fn json_search(
+    State(state): State<Arc<SearchState>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResponse>, (StatusCode, Json<ErrorResponse>)> {
+    match search(&state, &params) {
+        Ok(response) => Ok(Json(response)),
+        Err(e) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                position: e.position,
+            }),
+        )),
+    }
+}
+
+async fn json_sites(State(state): State<Arc<SearchState>>) -> Json<Vec<SiteCount>> {
+    Json(
+        state
+            .index
+            .website_document_counts(&state.websites)
+            .into_iter()
+            .map(|(website, documents)| SiteCount {
+                url: website.url.clone(),
+                documents,
+            })
+            .collect(),
+    )
+}
+
+async fn html_search(
+    State(state): State<Arc<SearchState>>,
+    Query(params): Query<SearchParams>,
+) -> impl IntoResponse {
+    // The bare landing page, before anyone has searched for anything.
+    if params.q.trim().is_empty() {
+        return Html(render_html(&params.q, None));
+    }
+
+    Html(render_html(&params.q, Some(&search(&state, &params))))
+}
+
+/// Runs `params.q` against the index, and resolves one page of the matching
+/// URLs to their stored documents.
+pub fn search(
+    state: &SearchState,
+    params: &SearchParams,
+) -> Result<SearchResponse, query::ParseError> {
+    let query = query::parse(&params.q, &state.index.analyzer)?;
+    let mut results = query.ranked_documents(&state.index);
+    if params.sort == Sort::Newest {
+        // A stable sort, so documents published at the same time (or not
+        // dated at all) stay in order of relevance.
+        results.sort_by_key(|(url, _)| Reverse(state.index.published_at(url)));
+    }
+
+    let terms = query.positive_terms();
+    let hits = results
+        .iter()
+        // Saturating, so a huge ?page= is just past the end rather than an overflow.
+        .skip(params.page.saturating_mul(PAGE_SIZE))
+        .take(PAGE_SIZE)
+        .map(|(url, score)| to_hit(state, url, *score, &terms))
+        .collect();
+
+    Ok(SearchResponse {
+        query: params.q.clone(),
+        page: params.page,
+        sort: params.sort,
+        total_hits: results.len(),
+        hits,
+    })
+}
+
+fn to_hit(state: &SearchState, url: &str, score: f32, terms: &[String]) -> SearchHit {
+    let document = Url::parse(url)
+        .ok()
+        .and_then(|u| net::load_document(&state.output_dir, &u));
+
+    match document {
+        Some(document) => SearchHit {
+            url: url.to_string(),
+            score,
+            snippet: snippet(&state.index.analyzer, &document.searchable_texts, terms),
+            title: document.title,
+            fetched_at_linux_epoch_secs: document.fetched_at_linux_epoch_secs,
+            author: document.author,
+            tags: document.tags,
+            published_at_linux_epoch_secs: document.published_at_linux_epoch_secs,
+        },
+        // The index knows about a document we no longer have on disk. We can
+        // still point the user at it, just without any of the trimmings.
+        None => SearchHit {
+            url: url.to_string(),
+            score,
+            title: url.to_string(),
+            fetched_at_linux_epoch_secs: 0,
+            snippet: String::new(),
+            author: None,
+            tags: vec![],
+            published_at_linux_epoch_secs: None,
+        },
+    }
+}
+
+/// Picks the text that mentions the most query terms, and trims it down to
+/// something that fits on a results page.
+fn snippet(analyzer: &StandardAnalyzer, texts: &[String], terms: &[String]) -> String {
+    let best = texts.iter().max_by_key(|text| {
+        let tokens = analyzer.analyze(text);
+        terms.iter().filter(|term| tokens.contains(term)).count()
+    });
+
+    match best {
+        None => String::new(),
+        Some(text) if text.chars().count() <= SNIPPET_LEN => text.clone(),
+        Some(text) => format!("{}…", text.chars().take(SNIPPET_LEN).collect::<String>()),
+    }
+}
+
+fn render_html(
+    query: &str,
+    response: Option<&Result<SearchResponse, query::ParseError>>,
+) -> String {
+    let mut html = format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>folklore</title></head><body>\n\
+         <form action=\"/\" method=\"get\"><input name=\"q\" value=\"{}\" autofocus> <button>Search</button></form>\n",
+        escape_html(query)
+    );
+
+    match response {
+        None => {}
+        Some(Err(e)) => html.push_str(&format!("<p>{}</p>\n", escape_html(&e.to_string()))),
+        Some(Ok(response)) => render_results(&mut html, response),
+    }
+
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn render_results(html: &mut String, response: &SearchResponse) {
+    html.push_str(&format!("<p>{} results</p>\n<ol>\n", response.total_hits));
+    for hit in &response.hits {
+        html.push_str(&format!(
+            "<li><a href=\"{url}\">{title}</a><br><small>{url}</small><p>{snippet}</p></li>\n",
+            url = escape_html(&hit.url),
+            title = escape_html(&hit.title),
+            snippet = escape_html(&hit.snippet),
+        ));
+    }
+    html.push_str("</ol>\n");
+
+    if response.page.saturating_add(1).saturating_mul(PAGE_SIZE) < response.total_hits {
+        html.push_str(&format!(
+            "<a href=\"/?q={}&page={}{}\">Next page</a>\n",
+            url::form_urlencoded::byte_serialize(response.query.as_bytes()).collect::<String>(),
+            response.page + 1,
+            match response.sort {
+                Sort::Relevance => "",
+                Sort::Newest => "&sort=newest",
+            }
+        ));
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::SearchableDocument;
+
+    fn document(url: &str, title: &str, texts: &[&str]) -> SearchableDocument {
+        SearchableDocument {
+            url: url.to_string(),
+            title: title.to_string(),
+            fetched_at_linux_epoch_secs: 1700000000,
+            searchable_texts: texts.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    async fn serve(documents: Vec<SearchableDocument>) -> (tempfile::TempDir, String) {
+        let output_dir = tempfile::tempdir().unwrap();
+        for document in &documents {
+            let url = Url::parse(&document.url).unwrap();
+            std::fs::write(
+                output_dir.path().join(net::url_to_filename(&url)),
+                serde_json::to_vec(document).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let index =
+            crate::index::build_index(output_dir.path(), StandardAnalyzer::default()).unwrap();
+        let state = Arc::new(SearchState {
+            index,
+            output_dir: output_dir.path().to_path_buf(),
+            websites: vec![
+                Website::new("https://danluu.com"),
+                Website::new("http://dtrace.org/blogs/ahl"),
+                Website::new("https://jm.dev"),
+            ],
+        });
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router(state).into_make_service()),
+        );
+
+        (output_dir, format!("http://{}", addr))
+    }
+
+    #[tokio::test]
+    async fn json_search_returns_stored_fields() {
+        let (_dir, base) = serve(vec![
+            document(
+                "https://danluu.com/latency/",
+                "Latency",
+                &["Nav bar", "Every programmer should know latency numbers"],
+            ),
+            document("https://jm.dev/", "Home", &["Nothing to see here"]),
+        ])
+        .await;
+
+        let response: SearchResponse = reqwest::get(format!("{}/search?q=latency", base))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(response.total_hits, 1);
+        assert_eq!(response.hits[0].url, "https://danluu.com/latency/");
+        assert_eq!(response.hits[0].title, "Latency");
+        assert_eq!(response.hits[0].fetched_at_linux_epoch_secs, 1700000000);
+        assert_eq!(
+            response.hits[0].snippet,
+            "Every programmer should know latency numbers"
+        );
+    }
+
+    #[tokio::test]
+    async fn html_search_escapes_results() {
+        let (_dir, base) = serve(vec![document(
+            "https://danluu.com/",
+            "<script>",
+            &["caches & latency"],
+        )])
+        .await;
+
+        let body = reqwest::get(format!("{}/?q=caches", base))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert!(body.contains("1 results"));
+        assert!(body.contains("&lt;script&gt;"));
+        assert!(body.contains("caches &amp; latency"));
+    }
+
+    #[tokio::test]
+    async fn huge_pages_are_empty() {
+        let (_dir, base) = serve(vec![document(
+            "https://danluu.com/",
+            "Latency",
+            &["latency"],
+        )])
+        .await;
+        let page = format!("q=latency&page={}", usize::MAX);
+
+        let response: SearchResponse = reqwest::get(format!("{}/search?{}", base, page))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!((response.total_hits, response.hits.len()), (1, 0));
+
+        let body = reqwest::get(format!("{}/?{}", base, page))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(body.contains("1 results"));
+        assert!(!body.contains("Next page"));
+    }
+
+    #[tokio::test]
+    async fn sorts_by_publication_date() {
+        let dated = |url: &str, published_at: Option<u64>, texts: &[&str]| SearchableDocument {
+            author: Some("Marc Brooker".to_string()),
+            tags: vec!["Architecture".to_string()],
+            published_at_linux_epoch_secs: published_at,
+            ..document(url, "Timeouts", texts)
+        };
+        let (_dir, base) = serve(vec![
+            dated(
+                "https://aws.example/old",
+                Some(1_500_000_000),
+                &["timeouts timeouts, retries and backoff"],
+            ),
+            dated(
+                "https://aws.example/new",
+                Some(1_600_000_000),
+                &["timeouts with jitter"],
+            ),
+            dated(
+                "https://aws.example/undated",
+                None,
+                &["timeouts everywhere, said nobody"],
+            ),
+        ])
+        .await;
+        let urls = |sort: &'static str| {
+            let base = base.clone();
+            async move {
+                let response: SearchResponse =
+                    reqwest::get(format!("{}/search?q=timeouts&sort={}", base, sort))
+                        .await
+                        .unwrap()
+                        .json()
+                        .await
+                        .unwrap();
+                response
+                    .hits
+                    .into_iter()
+                    .map(|hit| hit.url)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            urls("newest").await,
+            [
+                "https://aws.example/new",
+                "https://aws.example/old",
+                "https://aws.example/undated"
+            ]
+        );
+        assert_eq!(urls("relevance").await[0], "https://aws.example/old");
+
+        let response: SearchResponse = reqwest::get(format!("{}/search?q=timeouts", base))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let hit = &response.hits[0];
+        assert_eq!(hit.author.as_deref(), Some("Marc Brooker"));
+        assert_eq!(hit.tags, ["Architecture"]);
+        assert_eq!(hit.published_at_linux_epoch_secs, Some(1_500_000_000));
+    }
+
+    #[tokio::test]
+    async fn sites_reports_documents_per_website() {
+        let (_dir, base) = serve(vec![
+            document("https://danluu.com/", "Home", &["hi"]),
+            document("https://www.danluu.com/latency/", "Latency", &["hi"]),
+            document("http://dtrace.org/blogs/ahl/post", "DTrace", &["hi"]),
+            document("http://dtrace.org/blogs/bmc/post", "DTrace", &["hi"]),
+        ])
+        .await;
+
+        let sites: Vec<SiteCount> = reqwest::get(format!("{}/sites", base))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let counts: Vec<(&str, u64)> = sites
+            .iter()
+            .map(|s| (s.url.as_str(), s.documents))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("https://danluu.com", 2),
+                ("http://dtrace.org/blogs/ahl", 1),
+                ("https://jm.dev", 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::index::Index;
+use bincode::Options;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+gflags::define! {
+    /// The path of the index snapshot file to write (when indexing) or to
+    /// load (when serving).
+    pub --snapshot_path <SNAPSHOT_PATH> = "/home/jmq/src/folklore.dev/index.snapshot"
+}
+
+/// Identifies a file as a folklore index snapshot.
+const MAGIC: &[u8; 8] = b"FOLKLORE";
+
+/// The on-disk format version of index snapshots.
+///
+/// Bump this whenever the layout of `Index` changes. Old snapshots are then
+/// rejected at load time, rather than being misread into garbage.
+pub const FORMAT_VERSION: u32 = 10;
+
+const HEADER_LEN: usize = MAGIC.len() + std::mem::size_of::<u32>();
+
+/// How the `Index` after the header is encoded: bincode, with the fixed-width
+/// integers `bincode::serialize` writes.
+fn encoding() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+
+    /// The file doesn't start with the snapshot magic bytes.
+    NotASnapshot,
+
+    /// The snapshot was written by a build with a different format version.
+    VersionMismatch {
+        found: u32,
+        expected: u32,
+    },
+
+    /// The header was fine, but the payload couldn't be (de)serialized.
+    Encoding(bincode::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot I/O error: {}", e),
+            SnapshotError::NotASnapshot => write!(f, "file is not a folklore index snapshot"),
+            SnapshotError::VersionMismatch { found, expected } => write!(
+                f,
+                "snapshot format version {} doesn't match the supported version {}; rebuild the index",
+                found, expected
+            ),
+            SnapshotError::Encoding(e) => write!(f, "snapshot encoding error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(e: bincode::Error) -> Self {
+        SnapshotError::Encoding(e)
+    }
+}
+
+/// Writes `index` to `path` as a versioned snapshot.
+///
+/// The snapshot is written to a sibling temporary file first and then renamed
+/// into place, so a concurrently starting server never reads a half-written file.
+pub fn save(index: &Index, path: &Path) -> Result<(), SnapshotError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    encoding().serialize_into(&mut writer, index)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Loads an index snapshot previously written by `save`.
+///
+/// This skips re-reading and re-tokenizing the crawled documents, but it isn't
+/// free: the whole file is read and decoded into an owned `Index`, so startup
+/// time and memory grow with the index.
+pub fn load(path: &Path) -> Result<Index, SnapshotError> {
+    let bytes = std::fs::read(path)?;
+
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&bytes[MAGIC.len()..HEADER_LEN]);
+    let version = u32::from_le_bytes(version);
+    if version != FORMAT_VERSION {
+        return Err(SnapshotError::VersionMismatch {
+            found: version,
+            expected: FORMAT_VERSION,
+        });
+    }
+
+    // A corrupt length can't claim more than the file holds, so it can't make
+    // us allocate more than that either.
+    let payload = &bytes[HEADER_LEN..];
+    Ok(encoding()
+        .with_limit(payload.len() as u64)
+        .deserialize(payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::SearchableDocument;
+
+    fn snapshot_path() -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.snapshot");
+        (dir, path)
+    }
+
+    #[test]
+    fn round_trips_an_index() {
+        let mut index = Index::default();
+        index.index_document(SearchableDocument {
+            url: "https://danluu.com/tail-latency".to_string(),
+            title: "Tail latency".to_string(),
+            searchable_texts: vec!["The tail at scale.".to_string()],
+            ..SearchableDocument::default()
+        });
+        let (_dir, path) = snapshot_path();
+        save(&index, &path).unwrap();
+
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.document_codes, index.document_codes);
+        assert_eq!(loaded.word_codes, index.word_codes);
+        assert_eq!(loaded.document_lengths, index.document_lengths);
+        assert_eq!(
+            loaded.exact_ngram_match(vec!["tail".to_string(), "scale".to_string()]),
+            index.exact_ngram_match(vec!["tail".to_string(), "scale".to_string()])
+        );
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn rejects_corrupted_headers() {
+        let (_dir, path) = snapshot_path();
+        save(&Index::default(), &path).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[MAGIC.len()..HEADER_LEN].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        match load(&path) {
+            Err(SnapshotError::VersionMismatch { found, expected }) => {
+                assert_eq!((found, expected), (FORMAT_VERSION + 1, FORMAT_VERSION))
+            }
+            other => panic!("expected a version mismatch, got {:?}", other.err()),
+        }
+
+        bytes[0] = b'f';
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(load(&path), Err(SnapshotError::NotASnapshot)));
+
+        std::fs::write(&path, b"FOLK").unwrap();
+        assert!(matches!(load(&path), Err(SnapshotError::NotASnapshot)));
+    }
+
+    #[test]
+    fn rejects_corrupted_lengths() {
+        let (_dir, path) = snapshot_path();
+        save(&Index::default(), &path).unwrap();
+
+        // The index's first field is a map, which starts with its length.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(load(&path), Err(SnapshotError::Encoding(_))));
+    }
+}
@@ -0,0 +1,559 @@
+use crate::discovery::{self, Seed};
+use crate::net::{self, SearchableDocument};
+use crate::robots::RobotsCache;
+use crate::scheduler::Scheduler;
+use crate::scope::ScopePolicy;
+use crate::{builders_library, git, Repository, Website};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// One of the `[[sources]]` in `Config`, told apart by its `type`.
+///
+/// Websites and git repositories can also be listed under `[[websites]]` and
+/// `[[repositories]]`; either way they end up in `Config.websites` and
+/// `Config.repositories`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceConfig {
+    Website(Website),
+    Feed(Feed),
+    Git(Repository),
+    JsonDump(JsonDump),
+    LocalDir(LocalDir),
+    AwsBuildersLibrary(AwsBuildersLibrary),
+}
+
+/// An RSS or Atom feed, e.g. an aggregator's, whose entries are indexed even
+/// if they're on websites we don't crawl.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Feed {
+    pub url: String,
+}
+
+/// A JSON file of documents: an array of them, or one per line. Each needs at
+/// least a `url`; the other fields of `net::SearchableDocument` are optional.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonDump {
+    pub path: PathBuf,
+}
+
+/// A directory of markdown, text, HTML and PDF files.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LocalDir {
+    pub path: PathBuf,
+
+    /// Where the files can be browsed; each file is linked at its path beneath
+    /// this. Defaults to the `file://` URL of `path`.
+    pub url: Option<String>,
+}
+
+/// A saved response from the AWS directory API listing the Builders' Library,
+/// like `adhoc-sources/aws-builders-library/data.json`. Its articles are
+/// crawled with the author, publication date and tags the listing gives them,
+/// as part of whichever configured website they're on.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AwsBuildersLibrary {
+    pub path: PathBuf,
+}
+
+/// What a source gives the crawler.
+pub enum Item {
+    /// A document that's ready to be saved as it is.
+    Document(Box<SearchableDocument>),
+    /// A page to crawl as part of the `Website` whose `url` is `website`,
+    /// `depth` links away from its root.
+    Seed {
+        website: String,
+        seed: Seed,
+        depth: u32,
+    },
+}
+
+/// What sources fetch with.
+#[derive(Clone, Copy)]
+pub struct Crawler<'a> {
+    pub client: &'a reqwest::Client,
+    pub scheduler: &'a Scheduler,
+    pub robots: &'a RobotsCache,
+    pub scope: &'a ScopePolicy,
+    pub websites: &'a [Website],
+}
+
+/// Somewhere documents come from. The crawler saves every `Item::Document`
+/// into --output_dir and queues every `Item::Seed` in its frontier, whichever
+/// source they came from.
+pub trait Source: Sync {
+    /// Names the source in logs.
+    fn name(&self) -> String;
+
+    /// Everything the source has to offer.
+    fn items<'a>(&'a self, crawler: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>>;
+}
+
+impl Source for Website {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The website's root, and the pages its sitemaps and feeds list (which
+    /// links alone might never lead us to).
+    fn items<'a>(&'a self, crawler: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        async move {
+            let root = Url::parse(&self.url).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", self.url, e))
+            })?;
+            let seed = |seed, depth| Item::Seed {
+                website: self.url.clone(),
+                seed,
+                depth,
+            };
+
+            let discovered = discovery::discover(
+                crawler.client,
+                crawler.scheduler,
+                crawler.robots,
+                self,
+                crawler.scope,
+            )
+            .await;
+            Ok(std::iter::once(seed(Seed::new(root), 0))
+                .chain(discovered.into_iter().map(|s| seed(s, 1)))
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl Source for Repository {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn items<'a>(&'a self, _: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        async move {
+            Ok(git::ingest(self)?
+                .into_iter()
+                .map(|document| Item::Document(Box::new(document)))
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl Source for Feed {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Entries on a configured website are crawled like the rest of it. Any
+    /// others are indexed from what the feed says about them.
+    fn items<'a>(&'a self, crawler: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        async move {
+            let url = Url::parse(&self.url).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", self.url, e))
+            })?;
+            if !crawler
+                .robots
+                .is_allowed(crawler.client, crawler.scheduler, &url)
+                .await
+            {
+                return Ok(vec![]);
+            }
+            let scope = ScopePolicy::new(&[Website::new(&self.url)]);
+            let (url, body) = net::fetch_text(crawler.client, crawler.scheduler, &url, &scope)
+                .await
+                .map_err(|failure| {
+                    io::Error::new(io::ErrorKind::Other, format!("{:?}", failure))
+                })?;
+
+            let empty = ScopePolicy::new(&[]);
+            let mut items = vec![];
+            for entry in discovery::parse_feed_entries(&body, &url) {
+                let seed = match entry.seed {
+                    Some(seed) => seed,
+                    None => continue,
+                };
+                if let Some(website) = crawler.websites.iter().find(|w| w.in_scope(&seed.url)) {
+                    items.push(Item::Seed {
+                        website: website.url.clone(),
+                        seed: Seed {
+                            author: entry.author,
+                            ..seed
+                        },
+                        depth: 1,
+                    });
+                    continue;
+                }
+
+                let summary = entry.summary.unwrap_or_default();
+                let document = net::html_document(&summary, &seed.url, &empty);
+                items.push(Item::Document(Box::new(SearchableDocument {
+                    title: entry.title.unwrap_or(document.title),
+                    author: entry.author,
+                    published_at_linux_epoch_secs: seed.published_at_linux_epoch_secs,
+                    ..document
+                })));
+            }
+            Ok(items)
+        }
+        .boxed()
+    }
+}
+
+impl Source for JsonDump {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn items<'a>(&'a self, _: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        async move {
+            let contents = std::fs::read_to_string(&self.path)?;
+            let documents: Vec<SearchableDocument> = if contents.trim_start().starts_with('[') {
+                serde_json::from_str(&contents)?
+            } else {
+                contents
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(serde_json::from_str)
+                    .collect::<Result<_, _>>()?
+            };
+            // One bad entry shouldn't lose the rest of the dump.
+            Ok(documents
+                .into_iter()
+                .filter_map(|document| match Url::parse(&document.url) {
+                    Ok(_) => Some(Item::Document(Box::new(document))),
+                    Err(e) => {
+                        eprintln!(
+                            "Skipping `{}` in {}: it isn't a URL: {}",
+                            document.url,
+                            self.path.display(),
+                            e
+                        );
+                        None
+                    }
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl Source for LocalDir {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn items<'a>(&'a self, _: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        async move {
+            let base = crate::base_url(self.url.as_deref(), &self.path)?;
+
+            let mut items = vec![];
+            for path in files(&self.path)? {
+                let relative = path.strip_prefix(&self.path).unwrap_or(&path);
+                let name = relative.to_string_lossy().replace('\\', "/");
+                let mut url = base.clone();
+                url.path_segments_mut()
+                    .map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no path", base))
+                    })?
+                    .pop_if_empty()
+                    .extend(name.split('/'));
+
+                if let Some(document) = read_file(&path, &url, &name)? {
+                    items.push(Item::Document(Box::new(document)));
+                }
+            }
+            Ok(items)
+        }
+        .boxed()
+    }
+}
+
+impl Source for AwsBuildersLibrary {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn items<'a>(&'a self, crawler: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        async move {
+            let seeds = builders_library::parse(&std::fs::read_to_string(&self.path)?)?;
+            Ok(seeds
+                .into_iter()
+                .filter_map(|seed| {
+                    let website = crawler.websites.iter().find(|w| w.in_scope(&seed.url));
+                    if website.is_none() {
+                        println!("{} isn't on a configured website, skipping.", seed.url);
+                    }
+                    Some(Item::Seed {
+                        website: website?.url.clone(),
+                        seed,
+                        depth: 1,
+                    })
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl Source for SourceConfig {
+    fn name(&self) -> String {
+        match self {
+            SourceConfig::Website(website) => website.name(),
+            SourceConfig::Feed(feed) => feed.name(),
+            SourceConfig::Git(repository) => repository.name(),
+            SourceConfig::JsonDump(dump) => dump.name(),
+            SourceConfig::LocalDir(dir) => dir.name(),
+            SourceConfig::AwsBuildersLibrary(library) => library.name(),
+        }
+    }
+
+    fn items<'a>(&'a self, crawler: Crawler<'a>) -> BoxFuture<'a, io::Result<Vec<Item>>> {
+        match self {
+            SourceConfig::Website(website) => website.items(crawler),
+            SourceConfig::Feed(feed) => feed.items(crawler),
+            SourceConfig::Git(repository) => repository.items(crawler),
+            SourceConfig::JsonDump(dump) => dump.items(crawler),
+            SourceConfig::LocalDir(dir) => dir.items(crawler),
+            SourceConfig::AwsBuildersLibrary(library) => library.items(crawler),
+        }
+    }
+}
+
+/// Every file under `dir`, in a stable order, skipping hidden files and directories.
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            files.extend(self::files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Reads a file as a document, if it's a kind of file we index.
+fn read_file(path: &Path, url: &Url, name: &str) -> io::Result<Option<SearchableDocument>> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if std::fs::metadata(path)?.len() > git::MAX_DOC_BYTES as u64 && extension != "pdf" {
+        return Ok(None);
+    }
+
+    Ok(match extension.as_str() {
+        "html" | "htm" => Some(net::html_document(
+            &std::fs::read_to_string(path)?,
+            url,
+            &ScopePolicy::new(&[]),
+        )),
+        "pdf" => match net::pdf_document(&std::fs::read(path)?, url) {
+            Ok(document) => Some(document),
+            Err(failure) => {
+                eprintln!("Failed to read {}: {:?}", path.display(), failure);
+                None
+            }
+        },
+        _ if git::is_doc(name) => match std::fs::read_to_string(path) {
+            Ok(text) => Some(net::text_document(&text, url, name)),
+            Err(_) => None,
+        },
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn reads_typed_sources() {
+        let config: Config = toml::from_str(
+            r#"
+            [[websites]]
+            url = "https://danluu.com"
+
+            [[sources]]
+            type = "website"
+            url = "https://jm.dev"
+            max_depth = 2
+
+            [[sources]]
+            type = "git"
+            path = "/home/jmq/src/folklore.dev"
+
+            [[sources]]
+            type = "feed"
+            url = "https://lobste.rs/rss"
+
+            [[sources]]
+            type = "json_dump"
+            path = "dump.json"
+            "#,
+        )
+        .unwrap();
+
+        let websites: Vec<&str> = config.websites.iter().map(|w| w.url.as_str()).collect();
+        assert_eq!(websites, ["https://danluu.com", "https://jm.dev"]);
+        assert_eq!(config.websites[1].max_depth, Some(2));
+        assert_eq!(config.repositories.len(), 1);
+        assert_eq!(
+            config
+                .sources()
+                .iter()
+                .map(|s| s.name())
+                .collect::<Vec<_>>(),
+            [
+                "https://danluu.com",
+                "https://jm.dev",
+                "/home/jmq/src/folklore.dev",
+                "https://lobste.rs/rss",
+                "dump.json"
+            ]
+        );
+
+        assert!(
+            toml::from_str::<Config>("[[sources]]\ntype = \"ftp\"\nurl = \"ftp://x\"").is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_dumps_and_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &[u8]| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write(
+            "dump.jsonl",
+            br#"{"url": "https://example.com/one", "title": "One", "searchable_texts": ["First."]}
+                {"url": "not a url", "title": "Bad"}
+                {"url": "https://example.com/two"}"#,
+        );
+        write("notes/index.md", b"# Notes\n\nSome notes.");
+        write(
+            "notes/page.html",
+            b"<html><head><title>A page</title></head><body><p>Text.</p></body></html>",
+        );
+        write(
+            "paper.pdf",
+            &crate::pdf::tests::make_pdf(Some("A paper"), &[&["Abstract."]]),
+        );
+        write("image.png", b"\x89PNG");
+        write(".git/HEAD", b"ref: refs/heads/main");
+
+        let client = reqwest::Client::new();
+        let (scheduler, robots, scope) = (
+            Scheduler::new(1, &[]),
+            RobotsCache::new("test"),
+            ScopePolicy::new(&[]),
+        );
+        let crawler = Crawler {
+            client: &client,
+            scheduler: &scheduler,
+            robots: &robots,
+            scope: &scope,
+            websites: &[],
+        };
+        let titles = |items: Vec<Item>| -> Vec<(String, String)> {
+            items
+                .into_iter()
+                .map(|item| match item {
+                    Item::Document(document) => (document.url.clone(), document.title.clone()),
+                    Item::Seed { .. } => panic!("expected only documents"),
+                })
+                .collect()
+        };
+
+        let dump = JsonDump {
+            path: dir.path().join("dump.jsonl"),
+        };
+        assert_eq!(
+            titles(dump.items(crawler).await.unwrap()),
+            [
+                ("https://example.com/one".to_string(), "One".to_string()),
+                ("https://example.com/two".to_string(), "".to_string())
+            ]
+        );
+
+        let local = LocalDir {
+            path: dir.path().to_path_buf(),
+            url: Some("https://notes.example.com/".to_string()),
+        };
+        assert_eq!(
+            titles(local.items(crawler).await.unwrap()),
+            [
+                (
+                    "https://notes.example.com/notes/index.md".to_string(),
+                    "Notes".to_string()
+                ),
+                (
+                    "https://notes.example.com/notes/page.html".to_string(),
+                    "A page".to_string()
+                ),
+                (
+                    "https://notes.example.com/paper.pdf".to_string(),
+                    "A paper".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn seeds_builders_library_articles_on_configured_websites() {
+        let client = reqwest::Client::new();
+        let (scheduler, robots, scope) = (
+            Scheduler::new(1, &[]),
+            RobotsCache::new("test"),
+            ScopePolicy::new(&[]),
+        );
+        let crawler = Crawler {
+            client: &client,
+            scheduler: &scheduler,
+            robots: &robots,
+            scope: &scope,
+            websites: &[],
+        };
+
+        let library = AwsBuildersLibrary {
+            path: Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("adhoc-sources/aws-builders-library/data.json"),
+        };
+        assert!(library.items(crawler).await.unwrap().is_empty());
+        let websites = [Website::new("https://aws.amazon.com/builders-library")];
+        let items = library
+            .items(Crawler {
+                websites: &websites,
+                ..crawler
+            })
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 22);
+        match &items[0] {
+            Item::Seed {
+                website,
+                seed,
+                depth,
+            } => {
+                assert_eq!(website, "https://aws.amazon.com/builders-library");
+                assert_eq!(seed.author.as_deref(), Some("David Yanacek"));
+                assert_eq!(*depth, 1);
+            }
+            Item::Document(_) => panic!("expected only seeds"),
+        }
+    }
+}